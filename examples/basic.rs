@@ -33,12 +33,7 @@ async fn main() -> Result<(), anyhow::Error> {
         folder: "inbox".into(),
     };
 
-    let smtp = Smtp {
-        domain: "smtp.gmail.com".into(),
-        port: 587,
-        user: "user@gmail.com".into(),
-        password: "1234".into(),
-    };
+    let smtp = Smtp::new("smtp.gmail.com", 587, "user@gmail.com", "1234");
 
     mailfred::serve((imap, smtp), State::default(), count).await
 }
@@ -2,7 +2,10 @@ use std::sync::Arc;
 
 use mailfred::{
     router::Router,
-    service::{user_error, Request, Response, ResponseResult},
+    service::{
+        extract::{Csv, Subject},
+        handler, Request, Response, ResponseResult,
+    },
     transports::Gmail,
 };
 use tokio::sync::Mutex;
@@ -25,23 +28,10 @@ async fn echo(req: Request, _: State) -> ResponseResult {
     Response::ok(req.header, req.body)
 }
 
-async fn sum_csv(req: Request, _: State) -> ResponseResult {
-    let attachment = req
-        .attachment_iter()
-        .next()
-        .ok_or("Expected a csv attachment")
-        .map_err(user_error)?;
+async fn sum_csv(Subject(subject): Subject, Csv(rows): Csv<Vec<Vec<i64>>>) -> ResponseResult {
+    let total: i64 = rows.iter().flatten().sum();
 
-    let mut csv = csv::Reader::from_reader(attachment.content.as_slice());
-
-    let mut total = 0;
-    for record in csv.records() {
-        for elem in record.map_err(user_error)?.iter() {
-            total = elem.parse().map_err(user_error)?;
-        }
-    }
-
-    Response::ok(req.header, format!("Total: {}", total))
+    Response::ok(subject, format!("Total: {}", total))
 }
 
 #[tokio::main]
@@ -54,7 +44,7 @@ async fn main() -> Result<(), anyhow::Error> {
     let router = Router::default()
         .route("Count", count)
         .route("Echo", echo)
-        .route("Sum", sum_csv);
+        .route("Sum", handler(sum_csv));
 
     mailfred::serve(gmail, State::default(), router).await
 }
@@ -0,0 +1,249 @@
+#[cfg(feature = "dkim")]
+pub mod dkim;
+
+/// `authserv-id` of the results verified by mailfred itself.
+/// The `Authentication-Results` headers of a received message claiming it are
+/// ignored, as they can not come from mailfred.
+pub const AUTHSERV_ID: &str = "mailfred";
+
+/// Results of the checks that authenticate the sender of a message (RFC 8601),
+/// like DKIM, SPF and DMARC.
+///
+/// They are usually written by the server that received the message, in the
+/// `Authentication-Results` header, and they can also come from verifying the
+/// DKIM signatures in mailfred (see the `dkim` feature).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticationResults {
+    /// Identifier of the server that performed the checks, usually its domain.
+    pub authserv_id: String,
+    pub results: Vec<AuthResult>,
+}
+
+/// Result of a single check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthResult {
+    /// Checked method, in lowercase: `dkim`, `spf`, `dmarc`...
+    pub method: String,
+    /// Result of the check, in lowercase: `pass`, `fail`, `none`...
+    pub result: String,
+    /// Properties of the check, like `header.d` or `smtp.mailfrom`, and the
+    /// `reason`. The names are in lowercase.
+    pub properties: Vec<(String, String)>,
+}
+
+impl AuthResult {
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(property, _)| property.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn passes(&self) -> bool {
+        self.result == "pass"
+    }
+}
+
+impl AuthenticationResults {
+    /// Parses the value of an `Authentication-Results` header.
+    pub fn parse(value: &str) -> Option<AuthenticationResults> {
+        let value = normalize(value);
+        let mut statements = split_unquoted(&value, ';').into_iter();
+
+        let authserv_id = statements.next()?.split_whitespace().next()?.into();
+
+        let results = statements
+            .filter_map(|statement| {
+                let mut tokens = split_unquoted(statement, ' ')
+                    .into_iter()
+                    .filter(|token| !token.is_empty());
+
+                let (method, result) = tokens.next()?.split_once('=')?;
+                // The version of the method is not needed
+                let method = method.split('/').next()?.to_ascii_lowercase();
+
+                let properties = tokens
+                    .filter_map(|token| token.split_once('='))
+                    .map(|(name, value)| (name.to_ascii_lowercase(), unquote(value)))
+                    .collect();
+
+                Some(AuthResult {
+                    method,
+                    result: unquote(result).to_ascii_lowercase(),
+                    properties,
+                })
+            })
+            .collect();
+
+        Some(AuthenticationResults {
+            authserv_id,
+            results,
+        })
+    }
+
+    /// Results of the given method.
+    pub fn method<'a>(&'a self, method: &'a str) -> impl Iterator<Item = &'a AuthResult> {
+        self.results
+            .iter()
+            .filter(move |result| result.method.eq_ignore_ascii_case(method))
+    }
+
+    /// Tells if the domain of the address is authenticated: DMARC, DKIM or SPF
+    /// pass for that same domain, or for a parent one.
+    pub fn authenticates(&self, address: &str) -> bool {
+        let Some((_, domain)) = address.rsplit_once('@') else {
+            return false;
+        };
+
+        let aligned = |property: Option<&str>| {
+            property
+                .map(|value| value.rsplit('@').next().unwrap_or(value))
+                .is_some_and(|checked| is_same_or_subdomain(domain, checked))
+        };
+
+        // DMARC checks the domain of the `From` header, the one of the address,
+        // only trusted if the result tells which one it was
+        self.method("dmarc").any(|result| {
+            result.passes()
+                && result
+                    .property("header.from")
+                    .is_some_and(|from| is_same_or_subdomain(domain, from))
+        }) || self.method("dkim").any(|result| {
+            let signer = result.property("header.d").or(result.property("header.i"));
+            result.passes() && aligned(signer)
+        }) || self
+            .method("spf")
+            .any(|result| result.passes() && aligned(result.property("smtp.mailfrom")))
+    }
+}
+
+fn is_same_or_subdomain(domain: &str, parent: &str) -> bool {
+    let (domain, parent) = (domain.to_ascii_lowercase(), parent.to_ascii_lowercase());
+    domain == parent || domain.ends_with(&format!(".{}", parent))
+}
+
+/// Removes the comments, joins the lines, and removes the spaces around `=`,
+/// so the values can be split by spaces and `;`.
+fn normalize(value: &str) -> String {
+    let mut normalized = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+
+    for c in value.chars() {
+        match c {
+            '"' if depth == 0 => {
+                quoted = !quoted;
+                normalized.push(c);
+            }
+            '(' if !quoted => depth += 1,
+            ')' if !quoted && depth > 0 => depth -= 1,
+            _ if depth > 0 => (),
+            '=' if !quoted => {
+                normalized.truncate(normalized.trim_end().len());
+                normalized.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !normalized.ends_with(['=', ' ']) {
+                    normalized.push(' ');
+                }
+            }
+            c => normalized.push(c),
+        }
+    }
+
+    normalized
+}
+
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(value[start..i].trim());
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(value[start..].trim());
+
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GMAIL: &str = "mx.google.com;\r\n       \
+        dkim=pass header.i=@company.com header.s=20230601 header.b=abcd;\r\n       \
+        spf=pass (google.com: domain of bob@mail.company.com designates 1.2.3.4 as \
+        permitted sender) smtp.mailfrom=bob@mail.company.com;\r\n       \
+        dmarc=fail (p=NONE sp=NONE dis=NONE) header.from=other.com";
+
+    #[test]
+    fn parse() {
+        let results = AuthenticationResults::parse(GMAIL).unwrap();
+
+        assert_eq!(results.authserv_id, "mx.google.com");
+        assert_eq!(results.results.len(), 3);
+
+        let dkim = results.method("dkim").next().unwrap();
+        assert!(dkim.passes());
+        assert_eq!(dkim.property("header.s"), Some("20230601"));
+
+        let spf = results.method("spf").next().unwrap();
+        assert_eq!(spf.property("smtp.mailfrom"), Some("bob@mail.company.com"));
+
+        let dmarc = results.method("dmarc").next().unwrap();
+        assert_eq!(dmarc.result, "fail");
+        assert_eq!(
+            dmarc.properties,
+            vec![("header.from".into(), "other.com".into())]
+        );
+    }
+
+    #[test]
+    fn parse_quoted_values_and_none() {
+        let results = AuthenticationResults::parse(
+            "example.org 1; dkim = fail reason=\"bad; signature\" header.d=b.com",
+        )
+        .unwrap();
+        let dkim = results.method("dkim").next().unwrap();
+        assert_eq!(dkim.property("reason"), Some("bad; signature"));
+        assert_eq!(dkim.property("header.d"), Some("b.com"));
+
+        let none = AuthenticationResults::parse("example.org; none").unwrap();
+        assert_eq!(none.results, vec![]);
+    }
+
+    #[test]
+    fn authenticated_domains() {
+        let spf_only = AuthenticationResults::parse(
+            "mx.a.com; spf=pass smtp.mailfrom=bob@mail.company.com; dkim=fail header.d=company.com",
+        )
+        .unwrap();
+
+        assert!(spf_only.authenticates("bob@mail.company.com"));
+        assert!(!spf_only.authenticates("bob@company.com"));
+        assert!(!spf_only.authenticates("bob@evil.com"));
+
+        let dmarc =
+            AuthenticationResults::parse("mx.a.com; dmarc=pass header.from=company.com").unwrap();
+        assert!(dmarc.authenticates("bob@company.com"));
+        assert!(!dmarc.authenticates("bob@evil.com"));
+
+        // A pass whose domain is unknown is not trusted
+        let dmarc = AuthenticationResults::parse("mx.a.com; dmarc=pass").unwrap();
+        assert!(!dmarc.authenticates("bob@company.com"));
+    }
+}
@@ -0,0 +1,872 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{self, Ed25519KeyPair, RsaKeyPair},
+};
+
+use super::{AuthResult, AuthenticationResults, AUTHSERV_ID};
+use crate::{
+    message::Message,
+    mime::{self, with_crlf},
+};
+
+/// Signatures verified at most in a single message,
+/// to not let a message make us query the DNS without limit.
+const MAX_SIGNATURES: usize = 5;
+
+/// Answers the DNS queries needed to verify the DKIM signatures.
+///
+/// mailfred does not ship a DNS client: any one can be plugged in through
+/// this trait, and [`StaticResolver`] answers from fixed records, e.g. for
+/// tests or for a known set of senders.
+#[async_trait]
+pub trait Resolver: Send + Sync + 'static {
+    /// `TXT` records of a domain name, each one with its strings joined.
+    /// A name without records is not an error, it returns no records.
+    async fn txt(&self, name: &str) -> io::Result<Vec<String>>;
+}
+
+/// Answers from fixed records.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver(pub HashMap<String, Vec<String>>);
+
+impl StaticResolver {
+    /// Adds a `TXT` record to a domain name.
+    pub fn record(mut self, name: impl AsRef<str>, record: impl Into<String>) -> Self {
+        self.0
+            .entry(name.as_ref().to_ascii_lowercase())
+            .or_default()
+            .push(record.into());
+        self
+    }
+}
+
+#[async_trait]
+impl Resolver for StaticResolver {
+    async fn txt(&self, name: &str) -> io::Result<Vec<String>> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        Ok(self.0.get(&name).cloned().unwrap_or_default())
+    }
+}
+
+/// Verifies the DKIM signatures of an email (RFC 6376), signed with
+/// `rsa-sha256` or `ed25519-sha256` (RFC 8463).
+/// Returns a result for each signature, or a `none` result if there is none.
+pub async fn verify(email: &[u8], resolver: &dyn Resolver) -> Vec<AuthResult> {
+    let email = with_crlf(email);
+    let (fields, body) = split(&email);
+
+    let mut results = Vec::new();
+    for field in fields
+        .iter()
+        .filter(|field| field.name.eq_ignore_ascii_case("DKIM-Signature"))
+        .take(MAX_SIGNATURES)
+    {
+        results.push(match Signature::parse(field) {
+            Ok(signature) => signature.verify(&fields, body, resolver).await,
+            Err(reason) => result("permerror", None, reason),
+        });
+    }
+
+    if results.is_empty() {
+        results.push(result("none", None, ""));
+    }
+
+    results
+}
+
+/// Verifies the signatures of the message, adding their results to the ones
+/// already in it, under the [`AUTHSERV_ID`] of mailfred.
+/// Nothing is done for a message without its email.
+pub(crate) async fn verify_message(message: &mut Message, resolver: &dyn Resolver) {
+    let Some(raw) = &message.raw else {
+        return;
+    };
+
+    let results = verify(raw, resolver).await;
+
+    message.authentication.push(AuthenticationResults {
+        authserv_id: AUTHSERV_ID.into(),
+        results,
+    });
+}
+
+fn result(result: &str, signature: Option<&Signature>, reason: &str) -> AuthResult {
+    let mut properties = Vec::new();
+    if !reason.is_empty() {
+        properties.push(("reason".into(), reason.into()));
+    }
+    if let Some(signature) = signature {
+        properties.push(("header.d".into(), signature.domain.clone()));
+        properties.push(("header.s".into(), signature.selector.clone()));
+    }
+
+    AuthResult {
+        method: "dkim".into(),
+        result: result.into(),
+        properties,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    RsaSha256,
+    Ed25519Sha256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Canonicalization {
+    Simple,
+    Relaxed,
+}
+
+impl Canonicalization {
+    fn parse(name: &str) -> Result<Canonicalization, &'static str> {
+        match name {
+            "simple" => Ok(Canonicalization::Simple),
+            "relaxed" => Ok(Canonicalization::Relaxed),
+            _ => Err("unknown canonicalization"),
+        }
+    }
+}
+
+struct Signature<'a> {
+    field: &'a Field<'a>,
+    algorithm: Algorithm,
+    domain: String,
+    selector: String,
+    header_canonicalization: Canonicalization,
+    body_canonicalization: Canonicalization,
+    headers: Vec<String>,
+    body_hash: Vec<u8>,
+    signature: Vec<u8>,
+    length: Option<usize>,
+    expiration: Option<u64>,
+}
+
+impl<'a> Signature<'a> {
+    fn parse(field: &'a Field<'a>) -> Result<Signature<'a>, &'static str> {
+        let tags = tags(&field.value());
+        let tag = |name: &str| tags.get(name).map(String::as_str);
+        let required = |name: &str| tag(name).ok_or("missing tag");
+        let base64 = |name: &str| BASE64.decode(required(name)?).map_err(|_| "invalid base64");
+
+        if required("v")? != "1" {
+            return Err("unknown version");
+        }
+
+        let algorithm = match required("a")? {
+            "rsa-sha256" => Algorithm::RsaSha256,
+            "ed25519-sha256" => Algorithm::Ed25519Sha256,
+            _ => return Err("unsupported algorithm"),
+        };
+
+        let (header_canonicalization, body_canonicalization) =
+            match tag("c").unwrap_or("simple").split_once('/') {
+                Some((header, body)) => (
+                    Canonicalization::parse(header)?,
+                    Canonicalization::parse(body)?,
+                ),
+                None => (
+                    Canonicalization::parse(tag("c").unwrap_or("simple"))?,
+                    Canonicalization::Simple,
+                ),
+            };
+
+        let headers = required("h")?
+            .split(':')
+            .map(str::to_ascii_lowercase)
+            .collect::<Vec<_>>();
+
+        if !headers.iter().any(|header| header == "from") {
+            return Err("from header not signed");
+        }
+
+        let number = |name: &str| {
+            tag(name)
+                .map(|value| value.parse().map_err(|_| "invalid number"))
+                .transpose()
+        };
+
+        Ok(Signature {
+            field,
+            algorithm,
+            domain: required("d")?.to_ascii_lowercase(),
+            selector: required("s")?.into(),
+            header_canonicalization,
+            body_canonicalization,
+            headers,
+            body_hash: base64("bh")?,
+            signature: base64("b")?,
+            length: number("l")?.map(|length: u64| length as usize),
+            expiration: number("x")?,
+        })
+    }
+
+    async fn verify(
+        &self,
+        fields: &[Field<'_>],
+        body: &[u8],
+        resolver: &dyn Resolver,
+    ) -> AuthResult {
+        let fail = |result_name, reason| result(result_name, Some(self), reason);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        if self.expiration.is_some_and(|expiration| expiration < now) {
+            return fail("fail", "signature expired");
+        }
+
+        let mut body = canonical_body(body, self.body_canonicalization);
+        if let Some(length) = self.length {
+            if length > body.len() {
+                return fail("permerror", "body length beyond the body");
+            }
+            body.truncate(length);
+        }
+
+        if digest::digest(&digest::SHA256, &body).as_ref() != self.body_hash {
+            return fail("fail", "body hash did not verify");
+        }
+
+        let name = format!("{}._domainkey.{}", self.selector, self.domain);
+        let records = match resolver.txt(&name).await {
+            Ok(records) => records,
+            Err(_) => return fail("temperror", "key not available"),
+        };
+
+        let Some(key) = records.iter().find_map(|record| self.public_key(record)) else {
+            return fail("permerror", "no key for signature");
+        };
+
+        if key.is_empty() {
+            return fail("fail", "key revoked");
+        }
+
+        let data = signed_data(
+            fields,
+            &self.headers,
+            self.field,
+            self.header_canonicalization,
+        );
+
+        let verified = match self.algorithm {
+            Algorithm::RsaSha256 => signature::UnparsedPublicKey::new(
+                &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                rsa_public_key(&key).unwrap_or(&key),
+            )
+            .verify(&data, &self.signature),
+            Algorithm::Ed25519Sha256 => {
+                let hash = digest::digest(&digest::SHA256, &data);
+                signature::UnparsedPublicKey::new(&signature::ED25519, &key)
+                    .verify(hash.as_ref(), &self.signature)
+            }
+        };
+
+        match verified {
+            Ok(()) => result("pass", Some(self), ""),
+            Err(_) => fail("fail", "signature did not verify"),
+        }
+    }
+
+    /// Reads the key of a record, if it is a key for this signature.
+    fn public_key(&self, record: &str) -> Option<Vec<u8>> {
+        let tags = tags(record);
+
+        if tags.get("v").is_some_and(|version| version != "DKIM1") {
+            return None;
+        }
+
+        let kind = tags.get("k").map_or("rsa", String::as_str);
+        let expected = match self.algorithm {
+            Algorithm::RsaSha256 => "rsa",
+            Algorithm::Ed25519Sha256 => "ed25519",
+        };
+
+        if kind != expected {
+            return None;
+        }
+
+        BASE64.decode(tags.get("p")?).ok()
+    }
+}
+
+/// A header field of an email.
+pub(crate) struct Field<'a> {
+    pub name: &'a str,
+    /// The whole field, name and line break included
+    pub raw: &'a [u8],
+}
+
+impl Field<'_> {
+    fn value(&self) -> String {
+        // Tags are ASCII, so nothing is lost
+        let raw = String::from_utf8_lossy(self.raw);
+        raw.split_once(':')
+            .map(|(_, value)| value.into())
+            .unwrap_or_default()
+    }
+}
+
+/// Splits an email in its header fields and its body.
+pub(crate) fn split(email: &[u8]) -> (Vec<Field<'_>>, &[u8]) {
+    let (header, body) = mime::split_header(email);
+
+    let fields = mime::header_fields(header)
+        .into_iter()
+        .map(|raw| {
+            let name = raw.split(|&byte| byte == b':').next().unwrap_or_default();
+            Field {
+                name: std::str::from_utf8(name).unwrap_or_default().trim(),
+                raw,
+            }
+        })
+        .collect();
+
+    (fields, body)
+}
+
+/// Tags of a `tag=value` list, without the spaces in their values.
+fn tags(list: &str) -> HashMap<String, String> {
+    list.split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(name, value)| {
+            let value = value.chars().filter(|c| !c.is_whitespace()).collect();
+            (name.trim().to_owned(), value)
+        })
+        .collect()
+}
+
+/// Data covered by the signature of `signature_field`: the signed headers
+/// followed by the signature field itself, without its `b=` value.
+pub(crate) fn signed_data(
+    fields: &[Field<'_>],
+    headers: &[String],
+    signature_field: &Field<'_>,
+    canonicalization: Canonicalization,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    // A header signed several times takes its instances from the bottom up
+    let mut used = HashMap::<&str, usize>::new();
+    for header in headers {
+        let count = used.entry(header).or_default();
+        let instance = fields
+            .iter()
+            .rev()
+            .filter(|field| field.name.eq_ignore_ascii_case(header))
+            .nth(*count);
+
+        if let Some(field) = instance {
+            data.extend(canonical_header(field.raw, canonicalization));
+            *count += 1;
+        }
+    }
+
+    let unsigned = without_signature(signature_field.raw);
+    let mut field = canonical_header(&unsigned, canonicalization);
+    if field.ends_with(b"\r\n") {
+        field.truncate(field.len() - 2);
+    }
+    data.extend(field);
+
+    data
+}
+
+/// Removes the value of the `b=` tag of a signature field.
+fn without_signature(field: &[u8]) -> Vec<u8> {
+    let Some(colon) = field.iter().position(|&byte| byte == b':') else {
+        return field.to_vec();
+    };
+
+    let mut unsigned = field[..=colon].to_vec();
+    for (i, tag) in field[colon + 1..].split(|&byte| byte == b';').enumerate() {
+        if i > 0 {
+            unsigned.push(b';');
+        }
+
+        let equal = tag.iter().position(|&byte| byte == b'=');
+        match equal {
+            Some(equal) if trim(&tag[..equal]) == b"b" => unsigned.extend(&tag[..=equal]),
+            _ => unsigned.extend(tag),
+        }
+    }
+
+    unsigned
+}
+
+pub(crate) fn canonical_header(field: &[u8], canonicalization: Canonicalization) -> Vec<u8> {
+    if canonicalization == Canonicalization::Simple {
+        return field.to_vec();
+    }
+
+    let colon = field
+        .iter()
+        .position(|&byte| byte == b':')
+        .unwrap_or(field.len());
+
+    let mut header = trim(&field[..colon]).to_ascii_lowercase();
+    header.push(b':');
+
+    let value = field.get(colon + 1..).unwrap_or_default();
+    let unfolded = value
+        .iter()
+        .copied()
+        .filter(|&byte| byte != b'\r' && byte != b'\n')
+        .collect::<Vec<_>>();
+    header.extend(collapse_spaces(trim(&unfolded)));
+    header.extend(b"\r\n");
+
+    header
+}
+
+pub(crate) fn canonical_body(body: &[u8], canonicalization: Canonicalization) -> Vec<u8> {
+    let mut lines = body
+        .split_inclusive(|&byte| byte == b'\n')
+        .collect::<Vec<_>>();
+
+    let line_content = |line: &[u8]| -> Vec<u8> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        match canonicalization {
+            Canonicalization::Simple => line.to_vec(),
+            Canonicalization::Relaxed => collapse_spaces(trim_end(line)),
+        }
+    };
+
+    // The empty lines at the end are ignored
+    while lines
+        .last()
+        .is_some_and(|line| line_content(line).is_empty())
+    {
+        lines.pop();
+    }
+
+    let mut canonical = Vec::with_capacity(body.len());
+    for line in lines {
+        canonical.extend(line_content(line));
+        canonical.extend(b"\r\n");
+    }
+
+    if canonical.is_empty() && canonicalization == Canonicalization::Simple {
+        canonical.extend(b"\r\n");
+    }
+
+    canonical
+}
+
+/// Replaces each sequence of spaces and tabs by a single space.
+fn collapse_spaces(text: &[u8]) -> Vec<u8> {
+    let mut collapsed = Vec::with_capacity(text.len());
+    for &byte in text {
+        if byte == b' ' || byte == b'\t' {
+            if collapsed.last() != Some(&b' ') {
+                collapsed.push(b' ');
+            }
+        } else {
+            collapsed.push(byte);
+        }
+    }
+    collapsed
+}
+
+/// The text without its leading and trailing whitespace.
+fn trim(text: &[u8]) -> &[u8] {
+    let start = text
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(text.len());
+    trim_end(&text[start..])
+}
+
+/// The text without its trailing whitespace.
+fn trim_end(text: &[u8]) -> &[u8] {
+    let end = text
+        .iter()
+        .rposition(|byte| !byte.is_ascii_whitespace())
+        .map_or(0, |last| last + 1);
+    &text[..end]
+}
+
+/// Extracts the key of a `SubjectPublicKeyInfo` structure, where the RSA keys
+/// of the DKIM records are usually wrapped, from its DER encoding.
+fn rsa_public_key(info: &[u8]) -> Option<&[u8]> {
+    /// Reads a DER element: its tag, its content, and what follows it.
+    fn element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let (&tag, input) = input.split_first()?;
+        let (&length, mut input) = input.split_first()?;
+
+        let length = if length < 0x80 {
+            length as usize
+        } else {
+            let bytes = (length & 0x7f) as usize;
+            if bytes == 0 || bytes > 4 {
+                return None;
+            }
+            if input.len() < bytes {
+                return None;
+            }
+            let (length, rest) = input.split_at(bytes);
+            input = rest;
+            length
+                .iter()
+                .fold(0, |length, &byte| (length << 8) | byte as usize)
+        };
+
+        if input.len() < length {
+            return None;
+        }
+        let (content, rest) = input.split_at(length);
+        Some((tag, content, rest))
+    }
+
+    const SEQUENCE: u8 = 0x30;
+    const BIT_STRING: u8 = 0x03;
+
+    let (SEQUENCE, info, _) = element(info)? else {
+        return None;
+    };
+    let (SEQUENCE, _algorithm, info) = element(info)? else {
+        return None;
+    };
+    let (BIT_STRING, key, _) = element(info)? else {
+        return None;
+    };
+
+    // The first byte counts the unused bits of the string, none for a key
+    key.strip_prefix(&[0])
+}
+
+/// Headers signed, if the email has them.
+const SIGNED_HEADERS: &[&str] = &[
+    "from",
+    "to",
+    "cc",
+    "reply-to",
+    "subject",
+    "date",
+    "message-id",
+    "in-reply-to",
+    "references",
+    "mime-version",
+    "content-type",
+    "auto-submitted",
+    "precedence",
+    "x-autoreply",
+];
+
+/// Signs emails with DKIM (RFC 6376), for the receivers to verify them
+/// with the public key published in `<selector>._domainkey.<domain>`.
+///
+/// The signature covers the headers identifying the message, and its whole
+/// body, both canonicalized as `relaxed`. No DNS query is needed to sign.
+#[derive(Clone)]
+pub struct Signer {
+    domain: String,
+    selector: String,
+    key: Arc<Key>,
+}
+
+enum Key {
+    Rsa(RsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl Signer {
+    /// Signs with an RSA key (`rsa-sha256`), of 2048 bits at least,
+    /// in PKCS#8 or PKCS#1, either in PEM or in DER.
+    pub fn rsa(
+        domain: impl Into<String>,
+        selector: impl Into<String>,
+        key: impl AsRef<[u8]>,
+    ) -> io::Result<Signer> {
+        let key = der(key.as_ref())?;
+        let key = RsaKeyPair::from_pkcs8(&key)
+            .or_else(|_| RsaKeyPair::from_der(&key))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+
+        Ok(Signer::new(domain, selector, Key::Rsa(key)))
+    }
+
+    /// Signs with an Ed25519 key (`ed25519-sha256`, RFC 8463),
+    /// in PKCS#8, either in PEM or in DER.
+    pub fn ed25519(
+        domain: impl Into<String>,
+        selector: impl Into<String>,
+        key: impl AsRef<[u8]>,
+    ) -> io::Result<Signer> {
+        let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der(key.as_ref())?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+
+        Ok(Signer::new(domain, selector, Key::Ed25519(key)))
+    }
+
+    fn new(domain: impl Into<String>, selector: impl Into<String>, key: Key) -> Signer {
+        Signer {
+            domain: domain.into(),
+            selector: selector.into(),
+            key: Arc::new(key),
+        }
+    }
+
+    /// Returns the email with its `DKIM-Signature` header prepended.
+    pub fn sign(&self, email: &[u8]) -> Vec<u8> {
+        let email = with_crlf(email);
+        let (fields, body) = split(&email);
+
+        let mut headers = Vec::new();
+        for header in SIGNED_HEADERS {
+            for _ in fields
+                .iter()
+                .filter(|field| field.name.eq_ignore_ascii_case(header))
+            {
+                headers.push(header.to_string());
+            }
+        }
+
+        let body_hash = digest::digest(
+            &digest::SHA256,
+            &canonical_body(body, Canonicalization::Relaxed),
+        );
+
+        let algorithm = match *self.key {
+            Key::Rsa(_) => "rsa-sha256",
+            Key::Ed25519(_) => "ed25519-sha256",
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut field = format!(
+            "DKIM-Signature: v=1; a={}; c=relaxed/relaxed; d={}; s={};\r\n\tt={}; h={};\r\n\tbh={};\r\n\tb=",
+            algorithm,
+            self.domain,
+            self.selector,
+            now,
+            headers.join(":"),
+            BASE64.encode(body_hash),
+        );
+
+        let unsigned = Field {
+            name: "DKIM-Signature",
+            raw: field.as_bytes(),
+        };
+        let data = signed_data(&fields, &headers, &unsigned, Canonicalization::Relaxed);
+
+        let signature = match &*self.key {
+            Key::Rsa(key) => {
+                let mut signature = vec![0; key.public().modulus_len()];
+                key.sign(
+                    &signature::RSA_PKCS1_SHA256,
+                    &SystemRandom::new(),
+                    &data,
+                    &mut signature,
+                )
+                // Only fails for a wrong length of the signature
+                .expect("valid length of signature");
+                signature
+            }
+            Key::Ed25519(key) => key
+                .sign(digest::digest(&digest::SHA256, &data).as_ref())
+                .as_ref()
+                .to_vec(),
+        };
+
+        // Folded to keep the lines short, which the verification ignores
+        let signature = BASE64.encode(signature);
+        let lines = signature.as_bytes().chunks(72).collect::<Vec<_>>();
+        field.push_str(&String::from_utf8_lossy(&lines.join(&b"\r\n\t"[..])));
+        field.push_str("\r\n");
+
+        let mut signed = field.into_bytes();
+        signed.extend_from_slice(&email);
+        signed
+    }
+}
+
+/// Decodes a key in PEM, or returns it as is if it is already in DER.
+fn der(key: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    let Some(pem) = std::str::from_utf8(key)
+        .ok()
+        .filter(|pem| pem.trim_start().starts_with("-----BEGIN"))
+    else {
+        return Ok(Cow::Borrowed(key));
+    };
+
+    let content = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect::<String>();
+
+    BASE64
+        .decode(content.trim())
+        .map(Cow::Owned)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::KeyPair;
+
+    use super::*;
+
+    #[test]
+    fn body_canonicalization() {
+        let body = b"  C \r\nD \t E\r\n\r\n\r\n";
+
+        assert_eq!(
+            canonical_body(body, Canonicalization::Simple),
+            b"  C \r\nD \t E\r\n"
+        );
+        assert_eq!(
+            canonical_body(body, Canonicalization::Relaxed),
+            b" C\r\nD E\r\n"
+        );
+        assert_eq!(canonical_body(b"", Canonicalization::Simple), b"\r\n");
+        assert_eq!(canonical_body(b"", Canonicalization::Relaxed), b"");
+    }
+
+    /// The examples of RFC 6376, section 3.4.5
+    #[test]
+    fn header_canonicalization() {
+        assert_eq!(
+            canonical_header(b"A: X\r\n", Canonicalization::Relaxed),
+            b"a:X\r\n"
+        );
+        assert_eq!(
+            canonical_header(b"B : Y\t\r\n\tZ  \r\n", Canonicalization::Relaxed),
+            b"b:Y Z\r\n"
+        );
+        assert_eq!(
+            canonical_header(b"B : Y\t\r\n\tZ  \r\n", Canonicalization::Simple),
+            b"B : Y\t\r\n\tZ  \r\n"
+        );
+    }
+
+    #[test]
+    fn signature_value_removed() {
+        assert_eq!(
+            without_signature(b"DKIM-Signature: v=1; b=abc\r\n def; bh=xyz\r\n"),
+            b"DKIM-Signature: v=1; b=; bh=xyz\r\n"
+        );
+        assert_eq!(
+            without_signature(b"DKIM-Signature: v=1; bh=xyz; b=abc\r\n"),
+            b"DKIM-Signature: v=1; bh=xyz; b="
+        );
+    }
+
+    #[test]
+    fn fields_and_body() {
+        let email = with_crlf(b"From: a@b.com\nSubject: long\n subject\n\nbody\n");
+        let (fields, body) = split(&email);
+
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[1].name, "Subject");
+        assert_eq!(fields[1].raw, b"Subject: long\r\n subject\r\n");
+        assert_eq!(body, b"body\r\n");
+    }
+
+    /// The signed example of RFC 8463, section A.3, with its ed25519 key.
+    /// Its RSA signature is left out: it uses a key of 1024 bits, but it is
+    /// checked the same way.
+    #[tokio::test]
+    async fn verify_ed25519_example() {
+        let email = "DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed;\r\n \
+            d=football.example.com; i=@football.example.com;\r\n \
+            q=dns/txt; s=brisbane; t=1528637909; h=from : to :\r\n \
+            subject : date : message-id : from : subject : date;\r\n \
+            bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n \
+            b=/gCrinpcQOoIfuHNQIbq4pgh9kyIK3AQUdt9OdqQehSwhEIug4D11Bus\r\n \
+            Fa3bT3FY5OsU7ZbnKELq+eXdp1Q1Dw==\r\n\
+            From: Joe SixPack <joe@football.example.com>\r\n\
+            To: Suzie Q <suzie@shopping.example.net>\r\n\
+            Subject: Is dinner ready?\r\n\
+            Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r\n\
+            Message-ID: <20030712040037.46341.5F8J@football.example.com>\r\n\
+            \r\n\
+            Hi.\r\n\
+            \r\n\
+            We lost the game.  Are you hungry yet?\r\n\
+            \r\n\
+            Joe.\r\n";
+
+        let resolver = StaticResolver::default().record(
+            "brisbane._domainkey.football.example.com",
+            "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=",
+        );
+
+        let results = verify(email.as_bytes(), &resolver).await;
+        assert_eq!(results.len(), 1);
+        assert!(results[0].passes(), "{:?}", results[0]);
+        assert_eq!(
+            results[0].property("header.d"),
+            Some("football.example.com")
+        );
+
+        let tampered = email.replace("Is dinner ready?", "Is lunch ready?");
+        let results = verify(tampered.as_bytes(), &resolver).await;
+        assert_eq!(results[0].result, "fail");
+
+        let results = verify(email.as_bytes(), &StaticResolver::default()).await;
+        assert_eq!(results[0].result, "permerror");
+    }
+
+    #[tokio::test]
+    async fn unsigned_email() {
+        let results = verify(b"From: a@b.com\r\n\r\nbody\r\n", &StaticResolver::default()).await;
+        assert_eq!(results[0].result, "none");
+    }
+
+    #[tokio::test]
+    async fn results_of_mailfred_are_kept_apart() {
+        let server = AuthenticationResults::parse("mx.b.com; dkim=pass header.d=b.com").unwrap();
+        let mut message = Message {
+            address: "a@b.com".into(),
+            authentication: vec![server.clone()],
+            raw: Some(b"From: a@b.com\r\n\r\nbody\r\n".to_vec()),
+            ..Default::default()
+        };
+
+        verify_message(&mut message, &StaticResolver::default()).await;
+
+        assert_eq!(message.authentication[0], server);
+        assert_eq!(message.authentication[1].authserv_id, AUTHSERV_ID);
+        assert_eq!(message.authentication[1].results[0].result, "none");
+    }
+
+    #[tokio::test]
+    async fn sign_and_verify() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let public = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let resolver = StaticResolver::default().record(
+            "mail._domainkey.b.com",
+            format!(
+                "v=DKIM1; k=ed25519; p={}",
+                BASE64.encode(public.public_key())
+            ),
+        );
+
+        let signer = Signer::ed25519("b.com", "mail", pkcs8.as_ref()).unwrap();
+        let email = signer.sign(b"From: a@b.com\nTo: c@d.com\nSubject: Hi\n\nHello\n");
+
+        let results = verify(&email, &resolver).await;
+        assert!(results[0].passes(), "{:?}", results[0]);
+
+        assert!(Signer::rsa("b.com", "mail", pkcs8.as_ref()).is_err());
+    }
+}
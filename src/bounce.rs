@@ -0,0 +1,158 @@
+use async_trait::async_trait;
+
+/// A delivery status notification (RFC 3464): the report a mail server sends
+/// back when a message could not be delivered, or was delayed.
+///
+/// A report can cover several recipients. Only one is described here: the
+/// first one whose delivery failed, or the first one if none failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bounce {
+    /// Recipient as written in the original message, or as reported by the
+    /// server if the original one is not reported.
+    pub recipient: String,
+    /// What happened to the message: `failed`, `delayed`, `delivered`,
+    /// `relayed` or `expanded`.
+    pub action: String,
+    /// Status code, like `5.1.1`.
+    pub status: String,
+    /// Reply of the server that refused the message, if reported.
+    pub diagnostic: Option<String>,
+    /// `Message-ID` of the original message, if reported.
+    pub original_id: Option<String>,
+}
+
+impl Bounce {
+    /// Parses the content of a `message/delivery-status` part.
+    /// Returns `None` if it does not report any recipient.
+    pub fn parse(delivery_status: &str, original_id: Option<String>) -> Option<Bounce> {
+        // The first group of fields is about the message,
+        // each of the next ones is about a recipient.
+        let recipients = groups(delivery_status)
+            .skip(1)
+            .filter_map(|fields| {
+                let field = |name: &str| {
+                    fields
+                        .iter()
+                        .find(|(field, _)| field.eq_ignore_ascii_case(name))
+                        .map(|(_, value)| value.clone())
+                };
+
+                Some(Bounce {
+                    recipient: without_type(
+                        &field("Original-Recipient").or_else(|| field("Final-Recipient"))?,
+                    ),
+                    action: field("Action")?.to_ascii_lowercase(),
+                    status: field("Status")?,
+                    diagnostic: field("Diagnostic-Code").map(|code| without_type(&code)),
+                    original_id: original_id.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let failed = recipients.iter().position(Bounce::is_failure);
+        recipients.into_iter().nth(failed.unwrap_or(0))
+    }
+
+    /// Tells if the message will not be delivered.
+    pub fn is_failure(&self) -> bool {
+        self.action == "failed"
+    }
+
+    /// Tells if the status is a permanent one (`5.x.x`),
+    /// that will happen again if the message is sent again.
+    pub fn is_permanent(&self) -> bool {
+        self.status.starts_with('5')
+    }
+}
+
+/// Splits the fields in groups separated by blank lines,
+/// joining the values that span several lines.
+fn groups(content: &str) -> impl Iterator<Item = Vec<(String, String)>> + '_ {
+    content
+        .split("\r\n\r\n")
+        .flat_map(|group| group.split("\n\n"))
+        .map(|group| {
+            let mut fields: Vec<(String, String)> = Vec::new();
+            for line in group.lines() {
+                if line.starts_with([' ', '\t']) {
+                    if let Some((_, value)) = fields.last_mut() {
+                        value.push(' ');
+                        value.push_str(line.trim());
+                    }
+                } else if let Some((name, value)) = line.split_once(':') {
+                    fields.push((name.trim().into(), value.trim().into()));
+                }
+            }
+            fields
+        })
+        .filter(|fields| !fields.is_empty())
+}
+
+/// Removes the type that prefixes some values, like `rfc822;` or `smtp;`.
+fn without_type(value: &str) -> String {
+    match value.split_once(';') {
+        Some((_, value)) => value.trim().into(),
+        None => value.trim().into(),
+    }
+}
+
+/// Receives the bounces, instead of the service.
+/// See [`crate::config::Config::bounces`].
+#[async_trait]
+pub trait BounceHandler: Send + Sync + 'static {
+    async fn handle(&self, bounce: Bounce);
+}
+
+#[async_trait]
+impl<F> BounceHandler for F
+where
+    F: Fn(Bounce) + Send + Sync + 'static,
+{
+    async fn handle(&self, bounce: Bounce) {
+        (self)(bounce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = "Reporting-MTA: dns; mx.b.com\r\n\
+                          Arrival-Date: Mon, 1 Jan 2024 00:00:00 +0000\r\n\
+                          \r\n\
+                          Final-Recipient: rfc822; other@b.com\r\n\
+                          Action: delayed\r\n\
+                          Status: 4.4.1\r\n\
+                          \r\n\
+                          Final-Recipient: rfc822; nobody@b.com\r\n\
+                          Original-Recipient: rfc822; Nobody@b.com\r\n\
+                          Action: failed\r\n\
+                          Status: 5.1.1\r\n\
+                          Diagnostic-Code: smtp; 550 5.1.1 <nobody@b.com>: Recipient address\r\n \
+                          rejected: User unknown\r\n";
+
+    #[test]
+    fn failed_recipient_is_chosen() {
+        let bounce = Bounce::parse(REPORT, Some("1234@a.com".into())).unwrap();
+
+        assert_eq!(
+            bounce,
+            Bounce {
+                recipient: "Nobody@b.com".into(),
+                action: "failed".into(),
+                status: "5.1.1".into(),
+                diagnostic: Some(
+                    "550 5.1.1 <nobody@b.com>: Recipient address rejected: User unknown".into()
+                ),
+                original_id: Some("1234@a.com".into()),
+            }
+        );
+        assert!(bounce.is_failure() && bounce.is_permanent());
+    }
+
+    #[test]
+    fn report_without_recipients() {
+        assert_eq!(Bounce::parse("Reporting-MTA: dns; mx.b.com\n", None), None);
+        assert_eq!(Bounce::parse("", None), None);
+    }
+}
@@ -0,0 +1,256 @@
+use std::{sync::Arc, time::Duration};
+
+#[cfg(feature = "dkim")]
+use crate::authentication::dkim::Resolver;
+#[cfg(feature = "pgp")]
+use crate::openpgp::Keyring;
+#[cfg(feature = "smime")]
+use crate::smime::TrustStore;
+use crate::{
+    bounce::BounceHandler,
+    dead_letter::DeadLetterSink,
+    dedup::DedupStore,
+    queue::OutboundQueue,
+    service::{Panic, Response},
+};
+
+/// Builds the reply to a request whose service panicked.
+pub type PanicHandler = Arc<dyn Fn(&Panic) -> Response + Send + Sync>;
+
+/// Configuration of [`crate::serve_with`].
+/// The default configuration is the one used by [`crate::serve`].
+#[derive(Clone, Default)]
+pub struct Config {
+    pub reconnect: ReconnectPolicy,
+    /// Receives the replies refused for good by the outbound transport.
+    /// See [`crate::transport::Sender::is_permanent`].
+    /// They are only logged if there is no sink.
+    pub undeliverable: Option<Arc<dyn DeadLetterSink>>,
+    /// Receives the requests whose service failed with a system error,
+    /// to be replayed once the error is fixed.
+    pub failed_requests: Option<Arc<dyn DeadLetterSink>>,
+    /// Keeps the replies in disk until they are sent.
+    pub queue: Option<OutboundQueue>,
+    /// Builds the reply to a request whose service panicked, handled as a
+    /// system error. [`Panic::default_response`] is used if there is none.
+    /// The reply is then mapped by the layers of the router, if the service
+    /// is one, like any other system error.
+    pub on_panic: Option<PanicHandler>,
+    /// Time a service has to process a request. Once expired, the service is
+    /// cancelled, and the request is answered with a system error, mapped by
+    /// the layers of the router, if the service is one, e.g. with
+    /// [`crate::router::layers::ErrorHeader`].
+    /// A [`crate::router::Router`] can set shorter ones for each route.
+    pub timeout: Option<Duration>,
+    /// Remembers the messages already processed, to skip them if they are
+    /// received again.
+    pub dedup: Option<Arc<dyn DedupStore>>,
+    /// Gives to the service the messages sent automatically, and the ones
+    /// sent from the address of the server, and sends its replies.
+    /// By default, they are skipped, to not start an endless exchange of
+    /// replies. See [`crate::message::Automatic`].
+    pub reply_to_automatic: bool,
+    /// Receives the bounces, which are not given to the service then.
+    /// Without it, a bounce is skipped as any other automatic message, unless
+    /// [`Config::reply_to_automatic`] is set, where the service receives it
+    /// with a [`crate::message::Message::bounce`].
+    pub bounces: Option<Arc<dyn BounceHandler>>,
+    /// Verifies the DKIM signatures of the received messages, whose results
+    /// are added to the ones of the receiving server, under the
+    /// [`crate::authentication::AUTHSERV_ID`] of mailfred.
+    /// See [`crate::message::Message::authentication`].
+    #[cfg(feature = "dkim")]
+    pub dkim: Option<Arc<dyn Resolver>>,
+    /// Decrypts and verifies the received messages protected with OpenPGP.
+    /// See [`crate::openpgp::Keyring`].
+    #[cfg(feature = "pgp")]
+    pub pgp: Option<Arc<Keyring>>,
+    /// Verifies the received messages signed with S/MIME.
+    /// See [`crate::smime::TrustStore`].
+    #[cfg(feature = "smime")]
+    pub smime: Option<Arc<TrustStore>>,
+}
+
+impl Config {
+    /// Sets how the transports reconnect once their connection is lost.
+    pub fn reconnect(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Sets where the replies that can not be delivered are sent.
+    pub fn undeliverable(mut self, sink: impl DeadLetterSink) -> Self {
+        self.undeliverable = Some(Arc::new(sink));
+        self
+    }
+
+    /// Sets where the requests whose service failed are sent.
+    pub fn failed_requests(mut self, sink: impl DeadLetterSink) -> Self {
+        self.failed_requests = Some(Arc::new(sink));
+        self
+    }
+
+    /// Sets a queue to keep the replies in disk until they are sent.
+    pub fn queue(mut self, queue: OutboundQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Sets how the reply to a request whose service panicked is built.
+    pub fn on_panic(mut self, f: impl Fn(&Panic) -> Response + Send + Sync + 'static) -> Self {
+        self.on_panic = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the time a service has to process a request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets where the ids of the messages already processed are remembered.
+    pub fn dedup(mut self, store: impl DedupStore) -> Self {
+        self.dedup = Some(Arc::new(store));
+        self
+    }
+
+    /// Gives the messages sent automatically to the service, and answers them
+    /// too.
+    pub fn reply_to_automatic(mut self) -> Self {
+        self.reply_to_automatic = true;
+        self
+    }
+
+    /// Sets where the bounces are sent, instead of to the service.
+    pub fn bounces(mut self, handler: impl BounceHandler) -> Self {
+        self.bounces = Some(Arc::new(handler));
+        self
+    }
+
+    /// Verifies the DKIM signatures of the received messages,
+    /// querying the keys to the resolver.
+    #[cfg(feature = "dkim")]
+    pub fn verify_dkim(mut self, resolver: impl Resolver) -> Self {
+        self.dkim = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Decrypts and verifies the received messages with the keys of the
+    /// keyring. To protect the replies, see [`crate::transports::Smtp::pgp()`].
+    #[cfg(feature = "pgp")]
+    pub fn pgp(mut self, keyring: Keyring) -> Self {
+        self.pgp = Some(Arc::new(keyring));
+        self
+    }
+
+    /// Verifies the S/MIME signatures of the received messages against the
+    /// trusted certificates. To sign the replies, see
+    /// [`crate::transports::Smtp::smime()`].
+    #[cfg(feature = "smime")]
+    pub fn smime(mut self, trust: TrustStore) -> Self {
+        self.smime = Some(Arc::new(trust));
+        self
+    }
+}
+
+/// Decides how a lost connection is recovered.
+///
+/// Each attempt to connect waits twice the time of the previous one, starting
+/// at `base_delay` and never beyond `max_delay`. By default, it tries forever.
+/// Once the policy gives up, the error of the last attempt is returned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Time waited after the first failed attempt.
+    pub base_delay: Duration,
+    /// Maximum time waited between two attempts.
+    pub max_delay: Duration,
+    /// Fraction of each delay, from `0.0` to `1.0`, that is randomly removed
+    /// from it. It avoids many connections retrying at the same time.
+    pub jitter: f64,
+    /// Number of failed attempts before giving up.
+    pub max_attempts: Option<u32>,
+    /// Time disconnected before giving up.
+    pub max_downtime: Option<Duration>,
+    /// Gives up at the first attempt rejected for its credentials,
+    /// which retrying will not fix.
+    /// See [`crate::transport::Transport::is_auth_error`].
+    pub fatal_auth_errors: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.0,
+            max_attempts: None,
+            max_downtime: None,
+            fatal_auth_errors: false,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Time to wait after the given number of failed attempts, counting from
+    /// zero. `random` is expected to be in the range `[0, 1)`.
+    pub(crate) fn delay(&self, attempts: u32, random: f64) -> Duration {
+        // `checked_pow` is needed because `attempts` is unbounded:
+        // a long enough disconnection would overflow the exponent.
+        let delay = 2u32
+            .checked_pow(attempts)
+            .and_then(|factor| self.base_delay.checked_mul(factor))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        delay.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random)
+    }
+
+    /// Tells if the given number of failed attempts, during the given
+    /// downtime, are enough to give up.
+    pub(crate) fn gives_up(&self, attempts: u32, downtime: Duration) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+            || self.max_downtime.is_some_and(|max| downtime >= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_delay_capped() {
+        let policy = ReconnectPolicy::default();
+
+        assert_eq!(policy.delay(0, 0.0), Duration::from_secs(1));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_secs(8));
+        assert_eq!(policy.delay(6, 0.0), Duration::from_secs(60));
+        assert_eq!(policy.delay(u32::MAX, 0.0), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_delay() {
+        let policy = ReconnectPolicy {
+            jitter: 0.5,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.delay(3, 0.0), Duration::from_secs(8));
+        assert_eq!(policy.delay(3, 0.5), Duration::from_secs(6));
+        assert_eq!(policy.delay(10, 0.99), Duration::from_millis(30300));
+    }
+
+    #[test]
+    fn give_up() {
+        let forever = ReconnectPolicy::default();
+        assert!(!forever.gives_up(u32::MAX, Duration::MAX));
+
+        let limited = ReconnectPolicy {
+            max_attempts: Some(3),
+            max_downtime: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert!(!limited.gives_up(2, Duration::from_secs(59)));
+        assert!(limited.gives_up(3, Duration::ZERO));
+        assert!(limited.gives_up(0, Duration::from_secs(60)));
+    }
+}
@@ -1,26 +1,43 @@
-use std::time::{Duration, Instant};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, Instant},
+};
 
 use crate::{
+    config::ReconnectPolicy,
     message::Message,
     transport::{Inbound, Outbound, Receiver, Sender, Transport},
 };
 
-const MAX_RECONN_DELAY: Duration = Duration::from_secs(60);
 const LOG_AFTER: Duration = Duration::from_secs(60);
 
+/// Error of [`PerpetualConnection::send`].
+pub enum SendError {
+    /// The message was refused, and sending it again would fail the same way.
+    Rejected(anyhow::Error),
+    /// The connection was lost, and the reconnection policy gave up.
+    Disconnected(anyhow::Error),
+}
+
 /// Creates a perpetual connection using a transport.
-/// A perpetual connection is a connection that never ends and never fails.
-/// If there is some error on it, it will always try to reconnect
-/// and resend the message.
+/// A perpetual connection is a connection that survives its errors:
+/// if there is some error on it, it will try to reconnect and resend the
+/// message for as long as its [`ReconnectPolicy`] allows it.
 pub struct PerpetualConnection<T: Transport> {
     transport: T,
     conn: T::Connection,
+    policy: ReconnectPolicy,
     log_name: String,
 }
 
 impl<T: Transport> PerpetualConnection<T> {
     /// Creates a perpetual connected connection
-    pub async fn connect(transport: T, log_suffix: &str) -> Result<Self, T::Error> {
+    pub async fn connect(
+        transport: T,
+        policy: ReconnectPolicy,
+        log_suffix: &str,
+    ) -> Result<Self, T::Error> {
         let log_name = format!(
             "{}{}{}",
             T::NAME,
@@ -40,11 +57,12 @@ impl<T: Transport> PerpetualConnection<T> {
                 }
             },
             transport,
+            policy,
             log_name,
         })
     }
 
-    async fn force_connect(&mut self) {
+    async fn force_connect(&mut self) -> Result<(), T::Error> {
         let mut attempts: u32 = 0;
         let mut warned = false;
         let initial = Instant::now();
@@ -65,10 +83,28 @@ impl<T: Transport> PerpetualConnection<T> {
                         );
                     }
 
-                    break;
+                    break Ok(());
                 }
-                Err(_) => {
-                    if Instant::now() - initial >= LOG_AFTER && !warned {
+                Err(err) => {
+                    let downtime = Instant::now() - initial;
+                    attempts += 1;
+
+                    if self.policy.fatal_auth_errors && T::is_auth_error(&err) {
+                        log::error!("{}: credentials rejected: {}", self.log_name, err);
+                        break Err(err);
+                    }
+
+                    if self.policy.gives_up(attempts, downtime) {
+                        log::error!(
+                            "{}: can not reconnect after {} attempts: {}",
+                            self.log_name,
+                            attempts,
+                            err
+                        );
+                        break Err(err);
+                    }
+
+                    if downtime >= LOG_AFTER && !warned {
                         warned = true;
                         log::warn!(
                             "{}: disconnected for more than {} seconds",
@@ -77,18 +113,7 @@ impl<T: Transport> PerpetualConnection<T> {
                         );
                     }
 
-                    // Exponential backoff capped to `MAX_RECONN_DELAY`.
-                    // `checked_pow` is needed because `attempts` is unbounded:
-                    // a long enough disconnection would overflow the exponent.
-                    let delay = 2u64
-                        .checked_pow(attempts)
-                        .map(Duration::from_secs)
-                        .unwrap_or(MAX_RECONN_DELAY)
-                        .min(MAX_RECONN_DELAY);
-
-                    attempts += 1;
-
-                    tokio::time::sleep(delay).await;
+                    tokio::time::sleep(self.policy.delay(attempts - 1, random())).await;
                 }
             }
         }
@@ -96,20 +121,21 @@ impl<T: Transport> PerpetualConnection<T> {
 }
 
 impl<T: Inbound> PerpetualConnection<T> {
-    /// Receive without failing
-    pub async fn recv(&mut self) -> Message {
+    /// Receive, reconnecting if needed.
+    /// Only fails once the reconnection policy gives up.
+    pub async fn recv(&mut self) -> Result<Message, T::Error> {
         loop {
             match self.conn.recv().await {
                 Ok(msg) => {
                     log::debug!("{}: message received from '{}'", self.log_name, msg.address);
-                    break msg;
+                    break Ok(msg);
                 }
                 Err(_) => {
                     log::trace!(
                         "{}: receiver connection lost, Reconnecting...",
                         self.log_name
                     );
-                    self.force_connect().await
+                    self.force_connect().await?
                 }
             }
         }
@@ -117,13 +143,24 @@ impl<T: Inbound> PerpetualConnection<T> {
 }
 
 impl<T: Outbound> PerpetualConnection<T> {
-    /// Send without failing
-    pub async fn send(&mut self, msg: &Message) {
+    /// Send, reconnecting if needed.
+    /// Fails if the message is refused for good,
+    /// or once the reconnection policy gives up.
+    pub async fn send(&mut self, msg: &Message) -> Result<(), SendError> {
         loop {
             match self.conn.send(msg).await {
                 Ok(_) => {
                     log::debug!("{}: message sent to {}", self.log_name, msg.address);
-                    break;
+                    break Ok(());
+                }
+                Err(err) if T::OutboundQueue::is_permanent(&err) => {
+                    log::warn!(
+                        "{}: message to {} refused: {}",
+                        self.log_name,
+                        msg.address,
+                        err
+                    );
+                    break Err(SendError::Rejected(err.into()));
                 }
                 Err(_) => {
                     log::trace!(
@@ -131,9 +168,128 @@ impl<T: Outbound> PerpetualConnection<T> {
                         self.log_name,
                         msg.address
                     );
-                    self.force_connect().await
+                    self.force_connect()
+                        .await
+                        .map_err(|err| SendError::Disconnected(err.into()))?
                 }
             }
         }
     }
 }
+
+/// A number in the range `[0, 1)`, random enough to spread the reconnections.
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fmt,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use async_trait::async_trait;
+
+    use super::*;
+
+    #[derive(Debug)]
+    enum MockError {
+        Refused,
+        Lost,
+    }
+
+    impl fmt::Display for MockError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    impl std::error::Error for MockError {}
+
+    /// Fails every send with the given error, counting the connections.
+    struct MockTransport {
+        error: fn() -> MockError,
+        connections: Arc<AtomicU32>,
+    }
+
+    struct MockConnection(fn() -> MockError);
+
+    #[async_trait]
+    impl Transport for MockTransport {
+        const NAME: &'static str = "mock";
+
+        type Connection = MockConnection;
+        type Error = MockError;
+
+        async fn connect(&self) -> Result<MockConnection, MockError> {
+            match self.connections.fetch_add(1, Ordering::Relaxed) {
+                0 => Ok(MockConnection(self.error)),
+                _ => Err(MockError::Lost),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Sender for MockConnection {
+        type Error = MockError;
+
+        async fn send(&mut self, _: &Message) -> Result<(), MockError> {
+            Err((self.0)())
+        }
+
+        fn is_permanent(err: &MockError) -> bool {
+            matches!(err, MockError::Refused)
+        }
+    }
+
+    fn message() -> Message {
+        Message {
+            address: "user@domain.com".into(),
+            header: "".into(),
+            body: vec![],
+            ..Default::default()
+        }
+    }
+
+    async fn send(error: fn() -> MockError, policy: ReconnectPolicy) -> (SendError, u32) {
+        let connections = Arc::new(AtomicU32::new(0));
+        let transport = MockTransport {
+            error,
+            connections: connections.clone(),
+        };
+
+        let mut conn = PerpetualConnection::connect(transport, policy, "")
+            .await
+            .unwrap();
+
+        let err = conn.send(&message()).await.unwrap_err();
+        (err, connections.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test]
+    async fn refused_message_is_not_retried() {
+        let (err, connections) = send(|| MockError::Refused, ReconnectPolicy::default()).await;
+
+        assert!(matches!(err, SendError::Rejected(_)));
+        assert_eq!(connections, 1);
+    }
+
+    #[tokio::test]
+    async fn lost_connection_is_retried_until_the_policy_gives_up() {
+        let policy = ReconnectPolicy {
+            base_delay: Duration::from_millis(1),
+            max_attempts: Some(3),
+            ..Default::default()
+        };
+
+        let (err, connections) = send(|| MockError::Lost, policy).await;
+
+        assert!(matches!(err, SendError::Disconnected(_)));
+        assert_eq!(connections, 1 + 3);
+    }
+}
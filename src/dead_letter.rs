@@ -0,0 +1,143 @@
+use std::{fmt::Display, time::SystemTime};
+
+use async_trait::async_trait;
+#[cfg(all(feature = "imap", feature = "smtp"))]
+use mail_builder::headers::text::Text;
+#[cfg(all(feature = "imap", feature = "smtp"))]
+use mail_send::smtp::message::IntoMessage;
+
+#[cfg(all(feature = "imap", feature = "smtp"))]
+use crate::transports::{smtp::build_email, Imap};
+use crate::{
+    message::{Automatic, Kind, Message, Part},
+    transport::{Outbound, Sender},
+};
+
+/// A message that could not be handled, kept to be inspected or replayed
+/// later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    /// The message that could not be handled
+    pub message: Message,
+    /// Why it could not be handled
+    pub error: String,
+    /// When it was given up
+    pub time: SystemTime,
+}
+
+impl DeadLetter {
+    pub fn new(message: Message, error: impl Display) -> DeadLetter {
+        DeadLetter {
+            message,
+            error: error.to_string(),
+            time: SystemTime::now(),
+        }
+    }
+}
+
+/// Represents a destination for the dead letters.
+/// It is implemented by any function taking a [`DeadLetter`].
+#[async_trait]
+pub trait DeadLetterSink: Send + Sync + 'static {
+    async fn store(&self, letter: DeadLetter);
+}
+
+#[async_trait]
+impl<F> DeadLetterSink for F
+where
+    F: Fn(DeadLetter) + Send + Sync + 'static,
+{
+    async fn store(&self, letter: DeadLetter) {
+        (self)(letter)
+    }
+}
+
+/// Sends each dead letter by email to an address, through an outbound
+/// transport. The email contains the error and the parts of the message.
+pub struct Forward<O> {
+    pub transport: O,
+    pub address: String,
+}
+
+impl<O: Outbound> Forward<O> {
+    pub fn new(transport: O, address: impl Into<String>) -> Forward<O> {
+        Forward {
+            transport,
+            address: address.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl<O: Outbound> DeadLetterSink for Forward<O> {
+    async fn store(&self, letter: DeadLetter) {
+        let summary = Part {
+            kind: Kind::Text,
+            content: format!("From: {}\nError: {}", letter.message.address, letter.error)
+                .into_bytes(),
+        };
+
+        let message = Message {
+            address: self.address.clone(),
+            header: format!("Dead letter: {}", letter.message.header),
+            body: std::iter::once(summary)
+                .chain(letter.message.body)
+                .collect(),
+            automatic: Some(Automatic::Submitted("auto-generated".into())),
+            ..Default::default()
+        };
+
+        // A single attempt: a sink has no way to report its failures,
+        // and it must not keep the server busy on them.
+        let sent = match self.transport.connect().await {
+            Ok(mut conn) => conn.send(&message).await.map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        if let Err(err) = sent {
+            log::error!("{}: dead letter not forwarded: {}", O::NAME, err);
+        }
+    }
+}
+
+/// Appends each dead letter to an IMAP folder, as the email it came from,
+/// with the error in the `X-Mailfred-Error` header.
+///
+/// Replaying a dead letter is then a matter of moving it back to the folder
+/// read by the server, with any email client.
+#[cfg(all(feature = "imap", feature = "smtp"))]
+pub struct Folder(pub Imap);
+
+#[cfg(all(feature = "imap", feature = "smtp"))]
+#[async_trait]
+impl DeadLetterSink for Folder {
+    async fn store(&self, letter: DeadLetter) {
+        let imap = self.0.clone();
+        let folder = imap.folder.clone();
+        let time = letter
+            .time
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let email = build_email(&letter.message.address, &imap.user, &letter.message)
+            .date(time)
+            .header("X-Mailfred-Error", Text::new(letter.error.as_str()))
+            .into_message();
+
+        let stored = match email {
+            Ok(email) => {
+                let content = email.body.into_owned();
+                tokio::task::spawn_blocking(move || imap.append(&content))
+                    .await
+                    .map_err(|err| err.to_string())
+                    .and_then(|stored| stored.map_err(|err| err.to_string()))
+            }
+            Err(err) => Err(err.to_string()),
+        };
+
+        if let Err(err) = stored {
+            log::error!("imap: dead letter not stored in '{}': {}", folder, err);
+        }
+    }
+}
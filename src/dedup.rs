@@ -0,0 +1,275 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+
+/// Remembers the messages already processed, by their id, so a message
+/// delivered twice is answered only once.
+///
+/// A message can be delivered again by the inbound transport, for instance if
+/// the IMAP connection falls before the message is removed from the folder.
+/// Messages without an id are always processed.
+#[async_trait]
+pub trait DedupStore: Send + Sync + 'static {
+    /// Records an id. Returns `false` if it was already recorded.
+    async fn insert(&self, id: &str) -> bool;
+
+    /// Forgets an id, so its message can be processed again.
+    async fn remove(&self, id: &str);
+}
+
+/// Keeps in memory the ids of the last `capacity` messages processed.
+/// Once full, the least recently seen id is forgotten first.
+pub struct MemoryStore {
+    capacity: usize,
+    ids: Mutex<Recent>,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> MemoryStore {
+        MemoryStore {
+            capacity,
+            ids: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl DedupStore for MemoryStore {
+    async fn insert(&self, id: &str) -> bool {
+        self.ids.lock().unwrap().insert(id, self.capacity)
+    }
+
+    async fn remove(&self, id: &str) {
+        self.ids.lock().unwrap().remove(id);
+    }
+}
+
+/// Same as [`MemoryStore`], but also writing the ids to a file, so they are
+/// remembered after a restart.
+///
+/// Each id is appended as a line. The file is rewritten with only the
+/// remembered ids once it grows to twice the capacity.
+/// The file is written in a blocking task, out of the async runtime.
+pub struct FileStore {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+    capacity: usize,
+    state: Mutex<Backed>,
+}
+
+struct Backed {
+    ids: Recent,
+    file: File,
+    /// Lines written to the file, that can be more than the ids remembered
+    lines: usize,
+}
+
+impl FileStore {
+    /// Opens the file, creating it if it does not exist, and reads the ids
+    /// already recorded in it.
+    pub fn open(path: impl Into<PathBuf>, capacity: usize) -> io::Result<FileStore> {
+        let path = path.into();
+
+        let mut ids = Recent::default();
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                ids.insert(&line?, capacity);
+            }
+        }
+
+        let file = rewrite(&path, &ids)?;
+
+        Ok(FileStore {
+            shared: Arc::new(Shared {
+                path,
+                capacity,
+                state: Mutex::new(Backed {
+                    lines: ids.len(),
+                    ids,
+                    file,
+                }),
+            }),
+        })
+    }
+
+    /// Runs `f` with the id in a blocking task, as it writes the file.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        id: &str,
+        f: impl FnOnce(&Shared, &str) -> T + Send + 'static,
+    ) -> T {
+        let shared = self.shared.clone();
+        let id = id.to_owned();
+
+        tokio::task::spawn_blocking(move || f(&shared, &id))
+            .await
+            .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+    }
+}
+
+impl Shared {
+    fn write(&self, f: impl FnOnce(&mut Backed) -> io::Result<()>) {
+        if let Err(err) = f(&mut self.state.lock().unwrap()) {
+            log::error!(
+                "dedup: '{}' can not be written: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    fn insert(&self, id: &str) -> bool {
+        let mut inserted = false;
+
+        self.write(|state| {
+            inserted = state.ids.insert(id, self.capacity);
+            if inserted {
+                writeln!(state.file, "{}", id)?;
+                state.lines += 1;
+
+                if state.lines >= 2 * self.capacity {
+                    state.file = rewrite(&self.path, &state.ids)?;
+                    state.lines = state.ids.len();
+                }
+            }
+            Ok(())
+        });
+
+        inserted
+    }
+
+    fn remove(&self, id: &str) {
+        self.write(|state| {
+            if state.ids.remove(id) {
+                state.file = rewrite(&self.path, &state.ids)?;
+                state.lines = state.ids.len();
+            }
+            Ok(())
+        });
+    }
+}
+
+#[async_trait]
+impl DedupStore for FileStore {
+    async fn insert(&self, id: &str) -> bool {
+        self.blocking(id, |shared, id| shared.insert(id)).await
+    }
+
+    async fn remove(&self, id: &str) {
+        self.blocking(id, |shared, id| shared.remove(id)).await
+    }
+}
+
+/// Writes the file with only the given ids,
+/// returning it ready to append new ones.
+fn rewrite(path: &Path, ids: &Recent) -> io::Result<File> {
+    // Written aside and then renamed, so a file is never found half-written
+    let tmp = path.with_extension("tmp");
+    let mut content = String::new();
+    for id in ids.order.values() {
+        content.push_str(id);
+        content.push('\n');
+    }
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)?;
+
+    OpenOptions::new().append(true).open(path)
+}
+
+/// Ids in the order they were last seen.
+#[derive(Default)]
+struct Recent {
+    /// Position of each id in `order`
+    seen: HashMap<String, u64>,
+    /// Ids by the position they were last seen at, the most recent last
+    order: BTreeMap<u64, String>,
+    next: u64,
+}
+
+impl Recent {
+    fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    fn insert(&mut self, id: &str, capacity: usize) -> bool {
+        let next = self.next;
+        self.next += 1;
+
+        if let Some(position) = self.seen.get_mut(id) {
+            // Seen again: it becomes the most recent one
+            self.order.remove(position);
+            self.order.insert(next, id.into());
+            *position = next;
+            return false;
+        }
+
+        while self.order.len() >= capacity.max(1) {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        self.seen.insert(id.into(), next);
+        self.order.insert(next, id.into());
+        true
+    }
+
+    fn remove(&mut self, id: &str) -> bool {
+        let removed = self.seen.remove(id);
+        if let Some(position) = removed {
+            self.order.remove(&position);
+        }
+        removed.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_store_forgets_the_least_recent() {
+        let store = MemoryStore::new(2);
+
+        assert!(store.insert("a").await);
+        assert!(store.insert("b").await);
+        assert!(!store.insert("a").await);
+
+        // "b" is now the least recently seen
+        assert!(store.insert("c").await);
+        assert!(!store.insert("a").await);
+        assert!(store.insert("b").await);
+
+        store.remove("b").await;
+        assert!(store.insert("b").await);
+    }
+
+    #[tokio::test]
+    async fn file_store_remembers_after_reopening() {
+        let path = std::env::temp_dir().join(format!("mailfred-dedup-{}", std::process::id()));
+        fs::remove_file(&path).ok();
+
+        let store = FileStore::open(&path, 2).unwrap();
+        for id in ["a", "b", "c", "d", "e"] {
+            assert!(store.insert(id).await);
+        }
+        store.remove("e").await;
+        drop(store);
+
+        let store = FileStore::open(&path, 2).unwrap();
+        assert!(!store.insert("d").await);
+        assert!(store.insert("e").await);
+        assert!(store.insert("a").await);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -5,10 +5,23 @@
 #[cfg(doctest)]
 doc_comment::doctest!("../README.md");
 
+pub mod authentication;
+pub mod bounce;
+pub mod config;
 mod connection;
+pub mod dead_letter;
+pub mod dedup;
 pub mod message;
+#[cfg(any(feature = "dkim", feature = "pgp", feature = "smime"))]
+#[cfg_attr(not(any(feature = "pgp", feature = "smime")), allow(dead_code))]
+mod mime;
+#[cfg(feature = "pgp")]
+pub mod openpgp;
+pub mod queue;
 pub mod router;
 pub mod service;
+#[cfg(feature = "smime")]
+pub mod smime;
 pub mod transport;
 pub mod transports;
 pub mod util {
@@ -16,13 +29,15 @@ pub mod util {
     pub mod logger;
 }
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use connection::PerpetualConnection;
-use message::Message;
-use service::{ErrorResponse, Service};
-use tokio::sync::Mutex;
-use transport::{Connector, Inbound};
+use config::{Config, PanicHandler, ReconnectPolicy};
+use connection::{PerpetualConnection, SendError};
+use dead_letter::{DeadLetter, DeadLetterSink};
+use message::{Automatic, Message};
+use service::{ErrorResponse, Panic, Response, ResponseResult, Service};
+use tokio::sync::{mpsc, Mutex, Notify};
+use transport::{Connector, Inbound, Outbound, Transport};
 
 /// Main mailfred function.
 /// Initialize a server that will serve the given service throught the given
@@ -34,20 +49,90 @@ pub async fn serve<S: Clone + Send + 'static>(
     connector: impl Connector,
     shared_state: S,
     service: impl Service<S>,
+) -> Result<(), anyhow::Error> {
+    serve_with(connector, shared_state, service, Config::default()).await
+}
+
+/// Same as [`serve`], but with a custom configuration.
+/// It ends with an error if a transport can not be reconnected according to
+/// the reconnection policy of the configuration. With an outbound queue, only
+/// the inbound transport can end it: the replies wait in the queue instead.
+pub async fn serve_with<S: Clone + Send + 'static>(
+    connector: impl Connector,
+    shared_state: S,
+    service: impl Service<S>,
+    config: Config,
 ) -> Result<(), anyhow::Error> {
     let (inbound, outbound) = connector.split();
 
-    let mut receiver = PerpetualConnection::connect(inbound, "").await?;
-    let sender = PerpetualConnection::connect(outbound, "").await?;
+    // Answering them would answer our own replies, again and again.
+    let own_addresses = Arc::new(
+        [inbound.address(), outbound.address()]
+            .into_iter()
+            .flatten()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>(),
+    );
+
+    let mut receiver = PerpetualConnection::connect(inbound, config.reconnect.clone(), "").await?;
+    // With a queue, the sender must give up for the queue to retry later
+    let sender_policy = match &config.queue {
+        Some(queue) => queue.bound(config.reconnect),
+        None => config.reconnect,
+    };
+    let sender = PerpetualConnection::connect(outbound, sender_policy, "").await?;
 
     let shared_sender = Arc::new(Mutex::new(sender));
     let shared_service = Arc::new(service);
 
+    // The sender gives up inside the task of a message,
+    // but it is here where serving must end.
+    let (failure_tx, mut failure_rx) = mpsc::channel::<anyhow::Error>(1);
+
+    // With a queue, the replies are only sent from the queue, which never
+    // gives up on them because of the connection.
+    let queue = match config.queue {
+        Some(queue) => {
+            queue.create()?;
+
+            let pushed = Arc::new(Notify::new());
+            tokio::spawn(queue::flush(
+                queue.clone(),
+                shared_sender.clone(),
+                config.undeliverable.clone(),
+                pushed.clone(),
+            ));
+
+            Some((queue, pushed))
+        }
+        None => None,
+    };
+
     loop {
-        let input = receiver.recv().await;
+        let input = tokio::select! {
+            input = receiver.recv() => input?,
+            Some(err) = failure_rx.recv() => return Err(err),
+        };
+
         let sender = shared_sender.clone();
         let service = shared_service.clone();
         let state = shared_state.clone();
+        let failure = failure_tx.clone();
+        let undeliverable = config.undeliverable.clone();
+        let queue = queue.clone();
+        let failed_requests = config.failed_requests.clone();
+        let on_panic = config.on_panic.clone();
+        let timeout = config.timeout;
+        let dedup = config.dedup.clone();
+        let own_addresses = own_addresses.clone();
+        let reply_to_automatic = config.reply_to_automatic;
+        let bounces = config.bounces.clone();
+        #[cfg(feature = "dkim")]
+        let dkim = config.dkim.clone();
+        #[cfg(feature = "pgp")]
+        let pgp = config.pgp.clone();
+        #[cfg(feature = "smime")]
+        let smime = config.smime.clone();
 
         tokio::spawn(async move {
             let address = input.address.clone();
@@ -55,11 +140,84 @@ pub async fn serve<S: Clone + Send + 'static>(
 
             log::info!("Process message for '{}' with header '{}'", address, header);
 
-            let response = match service.call(input, state).await {
+            let unanswerable = if reply_to_automatic {
+                None
+            } else if own_addresses.contains(&address.to_lowercase()) {
+                Some("sent by this server".to_string())
+            } else {
+                let automatic = input.automatic.as_ref();
+                automatic.map(|automatic| format!("sent automatically: {:?}", automatic))
+            };
+
+            // The id is recorded before processing the message, so it is also
+            // skipped if received again meanwhile.
+            let id = dedup.as_ref().and(input.id.clone());
+            if let (Some(dedup), Some(id)) = (&dedup, &id) {
+                if !dedup.insert(id).await {
+                    log::info!(
+                        "Skip message '{}' from '{}': already processed",
+                        id,
+                        address
+                    );
+                    return Some(());
+                }
+            }
+
+            if let (Some(bounces), Some(bounce)) = (&bounces, &input.bounce) {
+                log::info!(
+                    "Bounce of a message to '{}': {} ({})",
+                    bounce.recipient,
+                    bounce.action,
+                    bounce.status
+                );
+                bounces.handle(bounce.clone()).await;
+                return Some(());
+            }
+
+            if let Some(reason) = unanswerable {
+                log::info!(
+                    "Skip message from '{}' with header '{}': {}",
+                    address,
+                    header,
+                    reason
+                );
+                return Some(());
+            }
+
+            #[cfg(any(feature = "dkim", feature = "pgp", feature = "smime"))]
+            let mut input = input;
+            #[cfg(feature = "dkim")]
+            if let Some(resolver) = &dkim {
+                authentication::dkim::verify_message(&mut input, &**resolver).await;
+            }
+            #[cfg(feature = "pgp")]
+            if let Some(keyring) = &pgp {
+                openpgp::open_message(&mut input, keyring);
+            }
+            #[cfg(feature = "smime")]
+            if let Some(trust) = &smime {
+                smime::verify_message(&mut input, trust);
+            }
+
+            // Only kept if someone wants it back
+            let request = failed_requests.as_ref().map(|_| input.clone());
+
+            let response = match call(service, input, state, timeout, on_panic).await? {
                 Ok(response) => response?,
                 Err(ErrorResponse::User(response)) => response,
                 Err(ErrorResponse::System(response)) => {
                     log::error!("System error: {}", response.body);
+
+                    // Processing it again must be possible once the error is
+                    // fixed, e.g. by replaying it from the failed requests.
+                    if let (Some(dedup), Some(id)) = (&dedup, &id) {
+                        dedup.remove(id).await;
+                    }
+
+                    if let (Some(sink), Some(request)) = (failed_requests, request) {
+                        sink.store(DeadLetter::new(request, &response.body)).await;
+                    }
+
                     response
                 }
             };
@@ -68,16 +226,122 @@ pub async fn serve<S: Clone + Send + 'static>(
                 address,
                 header: response.header,
                 body: response.body.0,
+                automatic: Some(Automatic::Submitted("auto-replied".into())),
+                ..Default::default()
             };
 
-            let mut sender = sender.lock().await;
-            sender.send(&output).await;
+            let queued = queue.is_some();
+            if let Some((queue, pushed)) = queue {
+                match queue.push(&output) {
+                    Ok(_) => {
+                        pushed.notify_one();
+                        return Some(());
+                    }
+                    Err(err) => log::error!("Reply not queued, sending it now: {}", err),
+                }
+            }
+
+            if let Err(err) = send(&sender, output, undeliverable).await {
+                // With a queue, the sender gives up sooner, but only for the
+                // queue to retry it later: serving goes on.
+                match queued {
+                    true => log::error!("Reply not sent: {}", err),
+                    false => {
+                        failure.try_send(err).ok();
+                    }
+                }
+            }
 
             Some(())
         });
     }
 }
 
+/// Calls the service, turning its panics and its timeout into system errors,
+/// mapped by the service as its own responses, e.g. by the layers of a router.
+/// It returns `None` only if the call is cancelled.
+async fn call<S: Send + 'static>(
+    service: Arc<impl Service<S>>,
+    input: Message,
+    state: S,
+    timeout: Option<Duration>,
+    on_panic: Option<PanicHandler>,
+) -> Option<ResponseResult> {
+    let address = input.address.clone();
+    let header = input.header.clone();
+
+    // The service runs in a task of its own to catch its panics,
+    // so the request is still answered and recorded.
+    let mut task = {
+        let service = service.clone();
+        tokio::spawn(async move { service.call(input, state).await })
+    };
+
+    let joined = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, &mut task).await {
+            Ok(joined) => joined,
+            Err(_) => {
+                task.abort();
+                log::error!(
+                    "Service timed out processing '{}' for '{}' after {:?}",
+                    header,
+                    address,
+                    timeout
+                );
+                let response = Err(ErrorResponse::System(Response::timeout()));
+                return Some(service.map_response(response));
+            }
+        },
+        None => task.await,
+    };
+
+    match joined {
+        Ok(result) => Some(result),
+        Err(err) if err.is_panic() => {
+            let panic = Panic::new(address, header, err.into_panic());
+
+            log::error!(
+                address = panic.address.as_str(),
+                header = panic.header.as_str(),
+                panic = panic.message.as_str();
+                "Service panicked processing '{}' for '{}': {}",
+                panic.header,
+                panic.address,
+                panic.message
+            );
+
+            let response = Err(ErrorResponse::System(match on_panic {
+                Some(on_panic) => on_panic(&panic),
+                None => panic.default_response(),
+            }));
+            Some(service.map_response(response))
+        }
+        // Cancelled, only possible if the runtime is shutting down
+        Err(_) => None,
+    }
+}
+
+/// Sends a reply, handing it to the sink if it is refused for good.
+/// Fails only if the connection can not be recovered.
+async fn send<O: Outbound>(
+    sender: &Mutex<PerpetualConnection<O>>,
+    output: Message,
+    undeliverable: Option<Arc<dyn DeadLetterSink>>,
+) -> Result<(), anyhow::Error> {
+    let result = sender.lock().await.send(&output).await;
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(SendError::Rejected(err)) => {
+            if let Some(sink) = undeliverable {
+                sink.store(DeadLetter::new(output, err)).await;
+            }
+            Ok(())
+        }
+        Err(SendError::Disconnected(err)) => Err(err),
+    }
+}
+
 /// Spawn a task that will consume the given inbound transport without
 /// processing the messages.
 /// Because the inbound method is expected to *extract* the message,
@@ -85,13 +349,56 @@ pub async fn serve<S: Clone + Send + 'static>(
 /// i.e. using [`imap`] it will remove all the emails in the configured folder.
 pub async fn spawn_consumer<T: Inbound>(imap: T, log_suffix: &str) -> Result<(), T::Error> {
     let log_suffix = format!("{}-consumer", log_suffix);
-    let mut consumer = PerpetualConnection::connect(imap, &log_suffix).await?;
+    let mut consumer =
+        PerpetualConnection::connect(imap, ReconnectPolicy::default(), &log_suffix).await?;
 
-    tokio::spawn(async move {
-        loop {
-            let _ = consumer.recv().await;
-        }
-    });
+    tokio::spawn(async move { while consumer.recv().await.is_ok() {} });
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{filters::Any, layers::ErrorHeader, Router};
+
+    async fn slow(_: Message, _: ()) -> ResponseResult {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Response::ok("Slow", "done")
+    }
+
+    async fn panics(_: Message, _: ()) -> ResponseResult {
+        panic!("service panicked")
+    }
+
+    #[tokio::test]
+    async fn errors_given_outside_the_router_are_mapped_by_its_layers() {
+        let request = Message {
+            address: "user@domain.com".into(),
+            ..Default::default()
+        };
+
+        let router = Router::default()
+            .route(Any, slow)
+            .layer(ErrorHeader("System error", "User error"));
+        let timeout = Some(Duration::from_millis(10));
+
+        match call(Arc::new(router), request.clone(), (), timeout, None).await {
+            Some(Err(ErrorResponse::System(response))) => {
+                assert_eq!(response.header, "System error")
+            }
+            _ => panic!("expected a system error"),
+        }
+
+        let router = Router::default()
+            .route(Any, panics)
+            .layer(ErrorHeader("System error", "User error"));
+
+        match call(Arc::new(router), request, (), None, None).await {
+            Some(Err(ErrorResponse::System(response))) => {
+                assert_eq!(response.header, "System error")
+            }
+            _ => panic!("expected a system error"),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use std::str::{self, Utf8Error};
 
+use crate::{authentication::AuthenticationResults, bounce::Bounce};
+
 /// Define the type of a message part
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Kind {
@@ -35,8 +37,25 @@ impl Part {
     }
 }
 
-/// Represents a message
+/// Tells that a message was sent by a program instead of by a person, and why.
+/// Answering such a message can start an endless exchange with that program,
+/// as it happens with an out-of-office reply.
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Automatic {
+    /// `Auto-Submitted` header with any value but `no`, like `auto-replied`.
+    Submitted(String),
+    /// `Precedence` header of a bulk message or a mailing list:
+    /// `bulk`, `list` or `junk`.
+    Precedence(String),
+    /// `X-Autoreply` header
+    Autoreply,
+    /// Sent by a mail system, like a bounce: with an empty return path, or
+    /// from `MAILER-DAEMON`.
+    MailSystem,
+}
+
+/// Represents a message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Message {
     /// Remote address where the message comes from or goes to
     pub address: String,
@@ -44,6 +63,46 @@ pub struct Message {
     pub header: String,
     /// Contains the information of a message
     pub body: Vec<Part>,
+    /// Unique identifier of the message (its `Message-ID`), if it has one.
+    /// A message sent without it gets a new one from the transport.
+    pub id: Option<String>,
+    /// Tells if the message was sent automatically.
+    /// A message sent with it carries the corresponding header.
+    pub automatic: Option<Automatic>,
+    /// Report of a message that could not be delivered, if this is one.
+    pub bounce: Option<Bounce>,
+    /// Results of the checks authenticating the sender, one for each
+    /// `Authentication-Results` header, from the topmost. Only the ones of a
+    /// known server can be trusted, by their `authserv_id`: any other could
+    /// have been written by the sender.
+    pub authentication: Vec<AuthenticationResults>,
+    /// Who signed the message, once its signature is verified.
+    pub signer: Option<Signer>,
+    /// Addresses the message was sent to, from its `To`, `Cc` and
+    /// `Delivered-To` headers, if the transport reads them.
+    /// Not used when a message is sent, which only goes to `address`.
+    pub recipients: Vec<String>,
+    /// The email as received, if the transport keeps it.
+    /// Not used when a message is sent.
+    pub raw: Option<Vec<u8>>,
+}
+
+/// Who signed a message, identified by the key that verified its signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signer {
+    /// An OpenPGP key, by its fingerprint in hexadecimal, and its user ids,
+    /// like `Alice <alice@company.com>`.
+    Pgp {
+        fingerprint: String,
+        user_ids: Vec<String>,
+    },
+    /// An S/MIME certificate, in DER, with its subject, like
+    /// `CN=Alice, O=Company`, and the addresses it is issued for.
+    Smime {
+        certificate: Vec<u8>,
+        subject: String,
+        addresses: Vec<String>,
+    },
 }
 
 impl Message {
@@ -0,0 +1,157 @@
+//! Helpers to read the header fields of an email, and to wrap its body in
+//! the multipart entities of PGP/MIME and S/MIME.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Splits an email in its header fields, without the ones describing its
+/// content, and the entity of its content: the `Content-*` fields and the
+/// body. That entity is the one signed or encrypted, while the rest of the
+/// fields stay in the email.
+pub(crate) fn split_content(email: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let email = with_crlf(email);
+    let (header, body) = split_header(&email);
+
+    let (mut fields, mut entity) = (Vec::new(), Vec::new());
+    for field in header_fields(header) {
+        if field.to_ascii_lowercase().starts_with(b"content-") {
+            entity.extend_from_slice(field);
+        } else {
+            fields.extend_from_slice(field);
+        }
+    }
+    entity.extend_from_slice(b"\r\n");
+    entity.extend_from_slice(body);
+
+    (fields, entity)
+}
+
+/// Wraps an entity in a `multipart/signed` one (RFC 1847), with the
+/// signature as its second part, described by the given fields.
+pub(crate) fn signed(
+    entity: &[u8],
+    protocol: &str,
+    micalg: &str,
+    signature_fields: &str,
+    signature: &str,
+) -> Vec<u8> {
+    let boundary = boundary();
+
+    let mut signed = format!(
+        "Content-Type: multipart/signed; boundary=\"{boundary}\";\r\n \
+         micalg=\"{micalg}\"; protocol=\"{protocol}\"\r\n\
+         \r\n\
+         --{boundary}\r\n"
+    )
+    .into_bytes();
+    signed.extend_from_slice(entity);
+    signed.extend(
+        format!(
+            "\r\n--{boundary}\r\n\
+             {signature_fields}\
+             \r\n\
+             {}\r\n\
+             --{boundary}--\r\n",
+            String::from_utf8_lossy(&with_crlf(signature.trim_end().as_bytes()))
+        )
+        .into_bytes(),
+    );
+
+    signed
+}
+
+/// A boundary that can not be found in the content: the content of the parts
+/// is encoded by `mail-builder`, or is base64, and none contains the prefix.
+pub(crate) fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    format!(
+        "mailfred-{:x}-{:x}",
+        now,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The line breaks of the signed content must be CRLF, as they are sent,
+/// but some stores keep the emails with LF only.
+pub(crate) fn with_crlf(content: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, &byte) in content.iter().enumerate() {
+        if byte == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+            converted.push(b'\r');
+        }
+        converted.push(byte);
+    }
+    converted
+}
+
+/// Splits an email, with CRLF line breaks, in its header and its body,
+/// without the empty line between them.
+pub(crate) fn split_header(email: &[u8]) -> (&[u8], &[u8]) {
+    if let Some(body) = email.strip_prefix(b"\r\n") {
+        return (&[], body);
+    }
+
+    match find(email, b"\r\n\r\n") {
+        Some(end) => (&email[..end + 2], &email[end + 4..]),
+        None => (email, &[]),
+    }
+}
+
+/// Splits a header in its fields, each one with its folded lines.
+pub(crate) fn header_fields(header: &[u8]) -> Vec<&[u8]> {
+    let mut fields: Vec<(usize, usize)> = Vec::new();
+    let mut position = 0;
+
+    while position < header.len() {
+        let end = find(&header[position..], b"\r\n").map_or(header.len(), |i| position + i + 2);
+
+        match (header[position], fields.last_mut()) {
+            // A folded line continues the previous field
+            (b' ' | b'\t', Some((_, field_end))) => *field_end = end,
+            _ => fields.push((position, end)),
+        }
+
+        position = end;
+    }
+
+    fields
+        .into_iter()
+        .map(|(start, end)| &header[start..end])
+        .collect()
+}
+
+pub(crate) fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_moves_to_the_entity() {
+        let (fields, entity) = split_content(
+            b"From: a@b.com\n\
+              Content-Type: text/plain;\n charset=utf-8\n\
+              Subject: Hi\n\
+              \n\
+              Hello\n",
+        );
+
+        assert_eq!(fields, b"From: a@b.com\r\nSubject: Hi\r\n");
+        assert_eq!(
+            entity,
+            b"Content-Type: text/plain;\r\n charset=utf-8\r\n\r\nHello\r\n"
+        );
+    }
+}
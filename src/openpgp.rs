@@ -0,0 +1,518 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use mail_parser::{Message as EmailParser, MimeHeaders, PartType};
+use pgp::{
+    composed::{
+        ArmorOptions, Deserializable, DetachedSignature, Message as PgpMessage, MessageBuilder,
+        SignedPublicKey, SignedSecretKey,
+    },
+    crypto::{hash::HashAlgorithm, sym::SymmetricKeyAlgorithm},
+    types::{KeyDetails, Password},
+};
+use rand::thread_rng;
+
+use crate::{
+    message::{Message, Part, Signer},
+    mime::{self, split_content, with_crlf},
+    transports::imap::read_body,
+};
+
+/// Nested encrypted or signed parts opened at most,
+/// to not let a message make us recurse without limit.
+const MAX_DEPTH: usize = 4;
+
+/// OpenPGP keys, to open the received messages and to protect the replies,
+/// following PGP/MIME (RFC 3156).
+///
+/// The received `multipart/encrypted` messages are decrypted with the secret
+/// keys, and the `multipart/signed` ones are verified with the public keys.
+/// Their body is replaced by the opened one, and the key that verified their
+/// signature is set as their [`Message::signer`]. A message that can not be
+/// opened is given as received.
+///
+/// The replies are signed with the first secret key, and encrypted to the
+/// public key of their recipient, if it is in the keyring, when enabled with
+/// [`Keyring::sign_replies`] and [`Keyring::encrypt_replies`].
+///
+/// Keys can be armored or binary. Their self-signatures are not checked, and
+/// any key of the keyring is trusted.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    secret_keys: Vec<(SignedSecretKey, String)>,
+    public_keys: Vec<SignedPublicKey>,
+    sign: bool,
+    encrypt: bool,
+}
+
+impl Keyring {
+    /// Loads all the keys of the files in a directory: the secret keys, which
+    /// must not be protected by a password, and the public ones.
+    pub fn load(dir: impl AsRef<Path>) -> io::Result<Keyring> {
+        let mut keyring = Keyring::default();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+
+            let content = fs::read(&path)?;
+            keyring = match SignedSecretKey::from_reader_single(&content[..]) {
+                Ok(_) => keyring.secret_key(&content, ""),
+                Err(_) => keyring.public_key(&content),
+            }
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        }
+
+        Ok(keyring)
+    }
+
+    /// Adds a secret key, unlocked with the given password, if it has one.
+    pub fn secret_key(
+        mut self,
+        key: impl AsRef<[u8]>,
+        password: impl Into<String>,
+    ) -> io::Result<Self> {
+        let (key, _) = SignedSecretKey::from_reader_single(key.as_ref()).map_err(invalid)?;
+        self.secret_keys.push((key, password.into()));
+        Ok(self)
+    }
+
+    /// Adds all the public keys of a file, or of a single key.
+    pub fn public_key(mut self, keys: impl AsRef<[u8]>) -> io::Result<Self> {
+        let (keys, _) = SignedPublicKey::from_reader_many(keys.as_ref()).map_err(invalid)?;
+        for key in keys {
+            self.public_keys.push(key.map_err(invalid)?);
+        }
+        Ok(self)
+    }
+
+    /// Signs the replies with the first secret key.
+    pub fn sign_replies(mut self) -> Self {
+        self.sign = true;
+        self
+    }
+
+    /// Encrypts the replies to the public key of their recipient. The replies
+    /// to recipients without a key in the keyring are sent unencrypted.
+    pub fn encrypt_replies(mut self) -> Self {
+        self.encrypt = true;
+        self
+    }
+
+    fn signer(&self, verifies: impl Fn(&SignedPublicKey) -> bool) -> Option<Signer> {
+        let key = self.public_keys.iter().find(|key| verifies(key))?;
+
+        Some(Signer::Pgp {
+            fingerprint: key.fingerprint().to_string(),
+            user_ids: key
+                .details
+                .users
+                .iter()
+                .map(|user| String::from_utf8_lossy(user.id.id()).into_owned())
+                .collect(),
+        })
+    }
+
+    /// Key of the recipient, by the address of its user ids.
+    fn recipient_key(&self, address: &str) -> Option<&SignedPublicKey> {
+        let address = address.to_lowercase();
+
+        self.public_keys.iter().find(|key| {
+            key.details.users.iter().any(|user| {
+                let id = String::from_utf8_lossy(user.id.id()).to_lowercase();
+                let id_address = match id.rsplit_once('<') {
+                    Some((_, rest)) => rest.trim_end_matches('>'),
+                    None => id.as_str(),
+                };
+                id_address.trim() == address
+            })
+        })
+    }
+
+    /// Opens an entity: an email, or one of its parts.
+    /// Returns `None` if it is neither encrypted nor signed.
+    fn open(&self, entity: &[u8], depth: usize) -> io::Result<Option<Opened>> {
+        let email = EmailParser::parse(entity).ok_or_else(|| invalid("unreadable entity"))?;
+        let root = email.root_part();
+
+        let is = |subtype: &str, protocol: &str| {
+            root.content_type().is_some_and(|content_type| {
+                content_type.ctype().eq_ignore_ascii_case("multipart")
+                    && content_type
+                        .subtype()
+                        .is_some_and(|s| s.eq_ignore_ascii_case(subtype))
+                    && content_type
+                        .attribute("protocol")
+                        .is_some_and(|p| p.eq_ignore_ascii_case(protocol))
+            })
+        };
+
+        let encrypted = is("encrypted", "application/pgp-encrypted");
+        let signed = is("signed", "application/pgp-signature");
+
+        if !encrypted && !signed {
+            return Ok(None);
+        }
+
+        if depth >= MAX_DEPTH {
+            return Err(invalid("too many nested parts"));
+        }
+
+        // The control part, and the one encrypted or signed
+        let children = match &root.body {
+            PartType::Multipart(children) => children,
+            _ => return Err(invalid("multipart without parts")),
+        };
+        let part = |i: usize| {
+            children
+                .get(i)
+                .and_then(|&id| email.part(id))
+                .ok_or_else(|| invalid("missing part"))
+        };
+
+        let (inner, signer) = if encrypted {
+            self.decrypt(part(1)?.contents())?
+        } else {
+            let content = part(0)?;
+            let content = with_crlf(&entity[content.offset_header..content.offset_end]);
+            let signer = self.verify(&content, part(1)?.contents())?;
+            (content, signer)
+        };
+
+        let opened = match self.open(&inner, depth + 1)? {
+            // The innermost signature is the one of the content
+            Some(opened) => Opened {
+                signer: opened.signer.or(signer),
+                ..opened
+            },
+            None => Opened {
+                body: read_body(
+                    &EmailParser::parse(&inner).ok_or_else(|| invalid("unreadable entity"))?,
+                ),
+                signer,
+            },
+        };
+
+        Ok(Some(opened))
+    }
+
+    fn decrypt(&self, armored: &[u8]) -> io::Result<(Vec<u8>, Option<Signer>)> {
+        let (message, _) = PgpMessage::from_armor(armored).map_err(invalid)?;
+
+        let passwords = self
+            .secret_keys
+            .iter()
+            .map(|(_, password)| Password::from(password.as_str()))
+            .collect::<Vec<_>>();
+        let keys = self.secret_keys.iter().map(|(key, _)| key).collect();
+
+        let mut message = message
+            .decrypt_with_keys(passwords.iter().collect(), keys)
+            .map_err(invalid)?;
+
+        if message.is_compressed() {
+            message = message.decompress().map_err(invalid)?;
+        }
+
+        let content = message.as_data_vec()?;
+
+        // Signed and encrypted at once (RFC 3156, section 6.2)
+        let signer = match message.is_signed() {
+            true => self.signer(|key| message.verify(key).is_ok()),
+            false => None,
+        };
+
+        Ok((with_crlf(&content), signer))
+    }
+
+    fn verify(&self, content: &[u8], armored: &[u8]) -> io::Result<Option<Signer>> {
+        let (signature, _) = DetachedSignature::from_armor_single(armored).map_err(invalid)?;
+
+        Ok(self.signer(|key| {
+            signature.verify(key, content).is_ok()
+                || key
+                    .public_subkeys
+                    .iter()
+                    .any(|subkey| signature.verify(&subkey.key, content).is_ok())
+        }))
+    }
+
+    /// Signs and encrypts an email, as configured.
+    pub(crate) fn protect(&self, email: &[u8], recipient: &str) -> io::Result<Vec<u8>> {
+        let (mut fields, mut entity) = split_content(email);
+
+        if self.sign {
+            if let Some((key, password)) = self.secret_keys.first() {
+                entity = sign(&entity, key, password)?;
+            }
+        }
+
+        if self.encrypt {
+            match self.recipient_key(recipient) {
+                Some(key) => entity = encrypt(&entity, key)?,
+                None => log::info!("pgp: no key to encrypt the message to '{}'", recipient),
+            }
+        }
+
+        fields.extend(entity);
+        Ok(fields)
+    }
+}
+
+/// Opens the message if it is encrypted or signed.
+/// Nothing is done for a message without its email.
+pub(crate) fn open_message(message: &mut Message, keyring: &Keyring) {
+    let Some(raw) = &message.raw else {
+        return;
+    };
+
+    match keyring.open(&with_crlf(raw), 0) {
+        Ok(Some(opened)) => {
+            if opened.signer.is_none() {
+                log::warn!("pgp: signature of '{}' not verified", message.address);
+            }
+            message.body = opened.body;
+            message.signer = opened.signer;
+        }
+        Ok(None) => (),
+        Err(err) => log::warn!("pgp: message of '{}' not opened: {}", message.address, err),
+    }
+}
+
+struct Opened {
+    body: Vec<Part>,
+    signer: Option<Signer>,
+}
+
+/// Wraps an entity in a `multipart/signed` one.
+fn sign(entity: &[u8], key: &SignedSecretKey, password: &str) -> io::Result<Vec<u8>> {
+    let signature = DetachedSignature::sign_binary_data(
+        thread_rng(),
+        &**key,
+        &Password::from(password),
+        HashAlgorithm::Sha256,
+        entity,
+    )
+    .and_then(|signature| signature.to_armored_string(ArmorOptions::default()))
+    .map_err(invalid)?;
+
+    Ok(mime::signed(
+        entity,
+        "application/pgp-signature",
+        "pgp-sha256",
+        "Content-Type: application/pgp-signature; name=\"signature.asc\"\r\n\
+         Content-Disposition: attachment; filename=\"signature.asc\"\r\n",
+        &signature,
+    ))
+}
+
+/// Wraps an entity in a `multipart/encrypted` one.
+fn encrypt(entity: &[u8], key: &SignedPublicKey) -> io::Result<Vec<u8>> {
+    let mut builder = MessageBuilder::from_bytes("", entity.to_vec())
+        .seipd_v1(thread_rng(), SymmetricKeyAlgorithm::AES256);
+
+    // An encryption subkey is preferred, but the primary key itself can be
+    // used too if it is able to encrypt, like an RSA one.
+    match key
+        .public_subkeys
+        .iter()
+        .find(|subkey| subkey.key.algorithm().can_encrypt())
+    {
+        Some(subkey) => builder.encrypt_to_key(thread_rng(), subkey),
+        None if key.primary_key.algorithm().can_encrypt() => {
+            builder.encrypt_to_key(thread_rng(), &key.primary_key)
+        }
+        None => return Err(invalid("no key to encrypt")),
+    }
+    .map_err(invalid)?;
+    let encrypted = builder
+        .to_armored_string(thread_rng(), ArmorOptions::default())
+        .map_err(invalid)?;
+
+    let boundary = mime::boundary();
+    Ok(format!(
+        "Content-Type: multipart/encrypted; boundary=\"{boundary}\";\r\n \
+         protocol=\"application/pgp-encrypted\"\r\n\
+         \r\n\
+         --{boundary}\r\n\
+         Content-Type: application/pgp-encrypted\r\n\
+         \r\n\
+         Version: 1\r\n\
+         \r\n\
+         --{boundary}\r\n\
+         Content-Type: application/octet-stream; name=\"encrypted.asc\"\r\n\
+         Content-Disposition: inline; filename=\"encrypted.asc\"\r\n\
+         \r\n\
+         {}\r\n\
+         --{boundary}--\r\n",
+        String::from_utf8_lossy(&mime::with_crlf(encrypted.trim_end().as_bytes()))
+    )
+    .into_bytes())
+}
+
+fn invalid(err: impl ToString) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use pgp::{
+        composed::{
+            EncryptionCaps, KeyType, SecretKeyParamsBuilder, SignedSecretKey, SubkeyParamsBuilder,
+        },
+        crypto::ecc_curve::ECCCurve,
+    };
+
+    use super::*;
+    use crate::transports::smtp::build_email;
+
+    fn key(user_id: &str) -> SignedSecretKey {
+        SecretKeyParamsBuilder::default()
+            .key_type(KeyType::Ed25519Legacy)
+            .can_certify(true)
+            .can_sign(true)
+            .primary_user_id(user_id.into())
+            .subkeys(vec![SubkeyParamsBuilder::default()
+                .key_type(KeyType::ECDH(ECCCurve::Curve25519Legacy))
+                .can_encrypt(EncryptionCaps::All)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap()
+            .generate(thread_rng())
+            .unwrap()
+    }
+
+    fn armored(key: &SignedSecretKey) -> (String, String) {
+        let options = ArmorOptions::default;
+        (
+            key.to_armored_string(options()).unwrap(),
+            key.to_public_key().to_armored_string(options()).unwrap(),
+        )
+    }
+
+    fn message(body: &str) -> Message {
+        Message {
+            address: "client@b.com".into(),
+            header: "Hi".into(),
+            body: vec![Part {
+                kind: crate::message::Kind::Text,
+                content: body.into(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// The server replies to the client, and the client then sends the
+    /// reply back as a request, to open it as the server would.
+    fn roundtrip(server: &Keyring, client: &Keyring) -> Message {
+        let reply = message("Hello\n");
+        let email = build_email("server@a.com", "client@b.com", &reply)
+            .write_to_vec()
+            .unwrap();
+        let protected = server.protect(&email, "client@b.com").unwrap();
+
+        let mut received = Message {
+            raw: Some(protected),
+            ..message("")
+        };
+        open_message(&mut received, client);
+        received
+    }
+
+    #[test]
+    fn sign_encrypt_and_open() {
+        let (server_secret, server_public) = armored(&key("Server <server@a.com>"));
+        let (client_secret, client_public) = armored(&key("Client <client@b.com>"));
+
+        let server = Keyring::default()
+            .secret_key(&server_secret, "")
+            .unwrap()
+            .public_key(&client_public)
+            .unwrap()
+            .sign_replies()
+            .encrypt_replies();
+        let client = Keyring::default()
+            .secret_key(&client_secret, "")
+            .unwrap()
+            .public_key(&server_public)
+            .unwrap();
+
+        let opened = roundtrip(&server, &client);
+        assert_eq!(opened.text_iter().next().unwrap().content, b"Hello\r\n");
+        assert!(matches!(
+            opened.signer,
+            Some(Signer::Pgp { user_ids, .. }) if user_ids == ["Server <server@a.com>"]
+        ));
+
+        // Without the key of the server, the signature is not verified
+        let client = Keyring::default().secret_key(&client_secret, "").unwrap();
+        let opened = roundtrip(&server, &client);
+        assert_eq!(opened.text_iter().next().unwrap().content, b"Hello\r\n");
+        assert_eq!(opened.signer, None);
+    }
+
+    #[test]
+    fn encrypt_without_encryption_subkey() {
+        // An RSA key encrypts with its primary key, it needs no subkey
+        let rsa = SecretKeyParamsBuilder::default()
+            .key_type(KeyType::Rsa(2048))
+            .can_certify(true)
+            .can_sign(true)
+            .can_encrypt(EncryptionCaps::All)
+            .primary_user_id("Client <client@b.com>".into())
+            .build()
+            .unwrap()
+            .generate(thread_rng())
+            .unwrap();
+        let (client_secret, client_public) = armored(&rsa);
+
+        let server = Keyring::default()
+            .public_key(&client_public)
+            .unwrap()
+            .encrypt_replies();
+        let client = Keyring::default().secret_key(&client_secret, "").unwrap();
+
+        let opened = roundtrip(&server, &client);
+        assert!(
+            String::from_utf8_lossy(opened.raw.as_ref().unwrap()).contains("multipart/encrypted")
+        );
+        assert_eq!(opened.text_iter().next().unwrap().content, b"Hello\r\n");
+
+        // A key that can not encrypt at all fails to protect the message
+        let signing_only = SecretKeyParamsBuilder::default()
+            .key_type(KeyType::Ed25519Legacy)
+            .can_certify(true)
+            .can_sign(true)
+            .primary_user_id("Client <client@b.com>".into())
+            .build()
+            .unwrap()
+            .generate(thread_rng())
+            .unwrap();
+        let (_, client_public) = armored(&signing_only);
+
+        let server = Keyring::default()
+            .public_key(&client_public)
+            .unwrap()
+            .encrypt_replies();
+        let email = build_email("server@a.com", "client@b.com", &message("Hello\n"))
+            .write_to_vec()
+            .unwrap();
+        assert!(server.protect(&email, "client@b.com").is_err());
+    }
+
+    #[test]
+    fn unprotected_messages_are_kept() {
+        let (_, client_public) = armored(&key("Client <client@b.com>"));
+        let server = Keyring::default().public_key(&client_public).unwrap();
+
+        let opened = roundtrip(&server, &Keyring::default());
+        assert_eq!(opened.body, message("").body);
+        assert_eq!(opened.signer, None);
+    }
+}
@@ -0,0 +1,602 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::{Mutex, Notify};
+
+use crate::{
+    config::ReconnectPolicy,
+    connection::{PerpetualConnection, SendError},
+    dead_letter::{DeadLetter, DeadLetterSink},
+    message::{Automatic, Kind, Message, Part},
+    transport::Outbound,
+};
+
+/// Identifies the format of the files, in case it changes in the future.
+const MAGIC: &[u8] = b"mailfred-queue-2\n";
+const EXTENSION: &str = "msg";
+/// Extension the files that can not be read are renamed to.
+const UNREADABLE_EXTENSION: &str = "unreadable";
+
+/// A directory where the replies are kept until they are sent.
+///
+/// Without it, a reply only lives in memory while it is sent, and it is lost if
+/// the server stops meanwhile. With it, every reply is written to the
+/// directory before being sent and removed once sent. The replies found in it
+/// when the server starts are sent first.
+///
+/// Once a reply fails to be sent because the connection can not be recovered
+/// (see [`crate::config::ReconnectPolicy`]), it is retried later instead of
+/// ending the server. The next replies wait for that retry too, instead of
+/// each one waiting through the reconnection policy. A reply still there after
+/// `max_age` is given up.
+/// A reconnection policy that never gives up, like the default one, would keep
+/// retrying the same reply instead, so with a queue, such a policy gives up
+/// after being disconnected for `retry_delay`.
+///
+/// A file of the directory that can not be read, e.g. because it was written
+/// by a version of mailfred with another format, is renamed with the
+/// `unreadable` extension and left there.
+#[derive(Debug, Clone)]
+pub struct OutboundQueue {
+    dir: PathBuf,
+    /// Time waited before retrying a reply whose sending failed.
+    pub retry_delay: Duration,
+    /// Age after which a reply not sent yet is given up.
+    pub max_age: Duration,
+}
+
+impl OutboundQueue {
+    /// Creates a queue in the given directory.
+    /// The directory is created when the server starts if it does not exist.
+    pub fn new(dir: impl Into<PathBuf>) -> OutboundQueue {
+        OutboundQueue {
+            dir: dir.into(),
+            retry_delay: Duration::from_secs(5 * 60),
+            max_age: Duration::from_secs(3 * 24 * 3600),
+        }
+    }
+
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub(crate) fn create(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)
+    }
+
+    /// The reconnection policy of the sender of the queue: the given one,
+    /// bounded to `retry_delay` disconnected if it never gives up.
+    pub(crate) fn bound(&self, policy: ReconnectPolicy) -> ReconnectPolicy {
+        match (policy.max_attempts, policy.max_downtime) {
+            (None, None) => ReconnectPolicy {
+                max_downtime: Some(self.retry_delay),
+                ..policy
+            },
+            _ => policy,
+        }
+    }
+
+    /// Stores a new reply, to be sent as soon as possible.
+    pub(crate) fn push(&self, message: &Message) -> io::Result<Entry> {
+        // Several replies can be pushed within the same clock tick
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        // Truncated to the precision stored in the file
+        let created = UNIX_EPOCH + Duration::from_millis(millis(SystemTime::now()));
+        let entry = Entry {
+            id: format!(
+                "{:020}-{:06}",
+                millis(created),
+                COUNTER.fetch_add(1, Ordering::Relaxed) % 1_000_000
+            ),
+            message: message.clone(),
+            created,
+            attempts: 0,
+            next_attempt: created,
+        };
+
+        self.write(&entry)?;
+        Ok(entry)
+    }
+
+    /// Reads all the stored replies, oldest first.
+    /// Files that can not be read are skipped, and renamed to not read them
+    /// again.
+    pub(crate) fn entries(&self) -> io::Result<Vec<Entry>> {
+        let mut paths = fs::read_dir(&self.dir)?
+            .filter_map(|dir_entry| dir_entry.ok().map(|dir_entry| dir_entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+            .collect::<Vec<_>>();
+
+        paths.sort();
+
+        Ok(paths
+            .into_iter()
+            .filter_map(|path| {
+                let id = path.file_stem()?.to_str()?.to_owned();
+                let entry = fs::read(&path)
+                    .ok()
+                    .and_then(|content| decode(id, &content));
+
+                if entry.is_none() {
+                    let aside = path.with_extension(UNREADABLE_EXTENSION);
+                    log::warn!(
+                        "queue: '{}' can not be read, moved to '{}'",
+                        path.display(),
+                        aside.display()
+                    );
+                    if let Err(err) = fs::rename(&path, &aside) {
+                        log::error!("queue: '{}' can not be moved: {}", path.display(), err);
+                    }
+                }
+
+                entry
+            })
+            .collect())
+    }
+
+    pub(crate) fn write(&self, entry: &Entry) -> io::Result<()> {
+        // Written aside and then renamed, so a file is never found half-written
+        let tmp = self.dir.join(format!("{}.tmp", entry.id));
+        fs::write(&tmp, encode(entry))?;
+        fs::rename(tmp, self.path(entry))
+    }
+
+    pub(crate) fn remove(&self, entry: &Entry) -> io::Result<()> {
+        fs::remove_file(self.path(entry))
+    }
+
+    fn path(&self, entry: &Entry) -> PathBuf {
+        self.dir.join(format!("{}.{}", entry.id, EXTENSION))
+    }
+}
+
+/// A reply stored in the queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    id: String,
+    pub message: Message,
+    pub created: SystemTime,
+    pub attempts: u32,
+    pub next_attempt: SystemTime,
+}
+
+/// Sends the replies of the queue forever, waking up when `pushed` is
+/// notified or when a failed reply must be retried.
+pub(crate) async fn flush<O: Outbound>(
+    queue: OutboundQueue,
+    sender: Arc<Mutex<PerpetualConnection<O>>>,
+    undeliverable: Option<Arc<dyn DeadLetterSink>>,
+    pushed: Arc<Notify>,
+) {
+    loop {
+        let entries = queue.entries().unwrap_or_else(|err| {
+            log::error!("queue: '{}' can not be read: {}", queue.dir.display(), err);
+            Vec::new()
+        });
+
+        for mut entry in entries {
+            let now = SystemTime::now();
+
+            if entry.next_attempt > now {
+                continue;
+            }
+
+            let age = now.duration_since(entry.created).unwrap_or_default();
+            let failure = if age >= queue.max_age {
+                log::warn!(
+                    "queue: reply to {} given up after {} attempts",
+                    entry.message.address,
+                    entry.attempts
+                );
+                Some(format!("not sent after {} attempts", entry.attempts))
+            } else {
+                match sender.lock().await.send(&entry.message).await {
+                    Ok(()) => None,
+                    Err(SendError::Rejected(err)) => Some(err.to_string()),
+                    Err(SendError::Disconnected(_)) => {
+                        entry.attempts += 1;
+                        entry.next_attempt = now + queue.retry_delay;
+
+                        if let Err(err) = queue.write(&entry) {
+                            log::error!("queue: reply can not be rescheduled: {}", err);
+                        }
+
+                        // The next replies would wait for the reconnection
+                        // policy again, holding the sender meanwhile.
+                        break;
+                    }
+                }
+            };
+
+            if let Err(err) = queue.remove(&entry) {
+                log::error!("queue: reply can not be removed: {}", err);
+            }
+
+            if let (Some(error), Some(sink)) = (failure, &undeliverable) {
+                sink.store(DeadLetter::new(entry.message, error)).await;
+            }
+        }
+
+        // Any retry is at most `retry_delay` ahead, so waking up at that
+        // rate is enough to not miss it.
+        tokio::select! {
+            _ = pushed.notified() => (),
+            _ = tokio::time::sleep(queue.retry_delay) => (),
+        }
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn encode(entry: &Entry) -> Vec<u8> {
+    fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+        buf.extend((bytes.len() as u64).to_le_bytes());
+        buf.extend(bytes);
+    }
+
+    fn put_optional(buf: &mut Vec<u8>, string: &Option<String>) {
+        match string {
+            Some(string) => {
+                buf.push(1);
+                put_bytes(buf, string.as_bytes());
+            }
+            None => buf.push(0),
+        }
+    }
+
+    let mut buf = MAGIC.to_vec();
+    buf.extend(millis(entry.created).to_le_bytes());
+    buf.extend(millis(entry.next_attempt).to_le_bytes());
+    buf.extend(entry.attempts.to_le_bytes());
+
+    let message = &entry.message;
+    put_bytes(&mut buf, message.address.as_bytes());
+    put_bytes(&mut buf, message.header.as_bytes());
+    put_optional(&mut buf, &message.id);
+    match &message.automatic {
+        None => buf.push(0),
+        Some(Automatic::Submitted(value)) => {
+            buf.push(1);
+            put_bytes(&mut buf, value.as_bytes());
+        }
+        Some(Automatic::Precedence(value)) => {
+            buf.push(2);
+            put_bytes(&mut buf, value.as_bytes());
+        }
+        Some(Automatic::Autoreply) => buf.push(3),
+        Some(Automatic::MailSystem) => buf.push(4),
+    }
+    buf.extend((message.body.len() as u64).to_le_bytes());
+
+    for part in &message.body {
+        match &part.kind {
+            Kind::Text => buf.push(0),
+            Kind::Html => buf.push(1),
+            Kind::Attachment(name) => {
+                buf.push(2);
+                put_bytes(&mut buf, name.as_bytes());
+            }
+        }
+        put_bytes(&mut buf, &part.content);
+    }
+
+    buf
+}
+
+fn decode(entry_id: String, content: &[u8]) -> Option<Entry> {
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        fn take(&mut self, len: usize) -> Option<&[u8]> {
+            if self.0.len() < len {
+                return None;
+            }
+            let (taken, rest) = self.0.split_at(len);
+            self.0 = rest;
+            Some(taken)
+        }
+
+        fn u8(&mut self) -> Option<u8> {
+            Some(self.take(1)?[0])
+        }
+
+        fn u32(&mut self) -> Option<u32> {
+            Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+        }
+
+        fn u64(&mut self) -> Option<u64> {
+            Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+        }
+
+        fn time(&mut self) -> Option<SystemTime> {
+            UNIX_EPOCH.checked_add(Duration::from_millis(self.u64()?))
+        }
+
+        fn bytes(&mut self) -> Option<Vec<u8>> {
+            let len = self.u64()?.try_into().ok()?;
+            Some(self.take(len)?.to_vec())
+        }
+
+        fn string(&mut self) -> Option<String> {
+            String::from_utf8(self.bytes()?).ok()
+        }
+
+        fn optional(&mut self) -> Option<Option<String>> {
+            match self.u8()? {
+                0 => Some(None),
+                1 => Some(Some(self.string()?)),
+                _ => None,
+            }
+        }
+    }
+
+    let mut reader = Reader(content.strip_prefix(MAGIC)?);
+
+    let created = reader.time()?;
+    let next_attempt = reader.time()?;
+    let attempts = reader.u32()?;
+    let address = reader.string()?;
+    let header = reader.string()?;
+    let id = reader.optional()?;
+    let automatic = match reader.u8()? {
+        0 => None,
+        1 => Some(Automatic::Submitted(reader.string()?)),
+        2 => Some(Automatic::Precedence(reader.string()?)),
+        3 => Some(Automatic::Autoreply),
+        4 => Some(Automatic::MailSystem),
+        _ => return None,
+    };
+
+    let mut body = Vec::new();
+    for _ in 0..reader.u64()? {
+        let kind = match reader.u8()? {
+            0 => Kind::Text,
+            1 => Kind::Html,
+            2 => Kind::Attachment(reader.string()?),
+            _ => return None,
+        };
+        body.push(Part {
+            kind,
+            content: reader.bytes()?,
+        });
+    }
+
+    reader.0.is_empty().then_some(Entry {
+        id: entry_id,
+        message: Message {
+            address,
+            header,
+            body,
+            id,
+            automatic,
+            // The rest is only known of received messages, not kept for sending
+            ..Default::default()
+        },
+        created,
+        attempts,
+        next_attempt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fmt,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::transport::{Sender, Transport};
+
+    fn message() -> Message {
+        Message {
+            address: "user@domain.com".into(),
+            header: "Report".into(),
+            body: vec![
+                Part {
+                    kind: Kind::Text,
+                    content: "text".into(),
+                },
+                Part {
+                    kind: Kind::Html,
+                    content: "<p>html</p>".into(),
+                },
+                Part {
+                    kind: Kind::Attachment("file.bin".into()),
+                    content: vec![0, 1, 2, 255],
+                },
+            ],
+            id: Some("1234@domain.com".into()),
+            automatic: Some(Automatic::Submitted("auto-replied".into())),
+            ..Default::default()
+        }
+    }
+
+    fn queue(name: &str) -> OutboundQueue {
+        let dir = std::env::temp_dir().join(format!("mailfred-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+
+        let queue = OutboundQueue::new(dir);
+        queue.create().unwrap();
+        queue
+    }
+
+    #[test]
+    fn encoding_roundtrip() {
+        let entry = Entry {
+            id: "id".into(),
+            message: message(),
+            created: UNIX_EPOCH + Duration::from_millis(1_000),
+            attempts: 3,
+            next_attempt: UNIX_EPOCH + Duration::from_millis(2_000),
+        };
+
+        assert_eq!(decode("id".into(), &encode(&entry)), Some(entry));
+    }
+
+    #[test]
+    fn truncated_or_foreign_content_is_not_decoded() {
+        let entry = Entry {
+            id: "id".into(),
+            message: message(),
+            created: UNIX_EPOCH,
+            attempts: 0,
+            next_attempt: UNIX_EPOCH,
+        };
+
+        let encoded = encode(&entry);
+        assert_eq!(decode("id".into(), &encoded[..encoded.len() - 1]), None);
+        assert_eq!(decode("id".into(), b"hello"), None);
+    }
+
+    #[test]
+    fn policies_never_giving_up_are_bounded() {
+        let queue = OutboundQueue::new("queue").retry_delay(Duration::from_secs(60));
+
+        let bounded = queue.bound(ReconnectPolicy::default());
+        assert!(bounded.gives_up(0, Duration::from_secs(60)));
+
+        let limited = ReconnectPolicy {
+            max_attempts: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(queue.bound(limited.clone()), limited);
+    }
+
+    #[test]
+    fn entries_in_push_order() {
+        let queue = queue("order");
+
+        let first = queue.push(&message()).unwrap();
+        let second = queue
+            .push(&Message {
+                header: "Second".into(),
+                ..message()
+            })
+            .unwrap();
+
+        // Not a reply, it must be ignored
+        fs::write(queue.dir().join("notes.txt"), "notes").unwrap();
+
+        // Written by another version, it is moved aside
+        let old = queue.dir().join("00000000000000000000-000000.msg");
+        fs::write(&old, "mailfred-queue-1\n").unwrap();
+
+        assert_eq!(queue.entries().unwrap(), vec![first.clone(), second]);
+        assert!(!old.exists());
+        assert!(old.with_extension("unreadable").exists());
+
+        queue.remove(&first).unwrap();
+        assert_eq!(queue.entries().unwrap().len(), 1);
+
+        fs::remove_dir_all(queue.dir()).unwrap();
+    }
+
+    #[derive(Debug)]
+    struct Lost;
+
+    impl fmt::Display for Lost {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "connection lost")
+        }
+    }
+
+    impl std::error::Error for Lost {}
+
+    /// Connects only once, and loses the connection on every send.
+    struct LostTransport {
+        connections: AtomicU32,
+        sends: Arc<AtomicU32>,
+    }
+
+    struct LostConnection(Arc<AtomicU32>);
+
+    #[async_trait]
+    impl Transport for LostTransport {
+        const NAME: &'static str = "lost";
+
+        type Connection = LostConnection;
+        type Error = Lost;
+
+        async fn connect(&self) -> Result<LostConnection, Lost> {
+            match self.connections.fetch_add(1, Ordering::Relaxed) {
+                0 => Ok(LostConnection(self.sends.clone())),
+                _ => Err(Lost),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Sender for LostConnection {
+        type Error = Lost;
+
+        async fn send(&mut self, _: &Message) -> Result<(), Lost> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Err(Lost)
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnected_sender_is_tried_once_per_pass() {
+        let queue = queue("disconnected").retry_delay(Duration::from_secs(60));
+        for _ in 0..3 {
+            queue.push(&message()).unwrap();
+        }
+
+        let sends = Arc::new(AtomicU32::new(0));
+        let transport = LostTransport {
+            connections: AtomicU32::new(0),
+            sends: sends.clone(),
+        };
+        let policy = ReconnectPolicy {
+            base_delay: Duration::from_millis(1),
+            max_attempts: Some(1),
+            ..Default::default()
+        };
+        let sender = PerpetualConnection::connect(transport, policy, "")
+            .await
+            .unwrap();
+
+        let pass = flush(
+            queue.clone(),
+            Arc::new(Mutex::new(sender)),
+            None,
+            Arc::new(Notify::new()),
+        );
+        tokio::time::timeout(Duration::from_millis(200), pass)
+            .await
+            .unwrap_err();
+
+        assert_eq!(sends.load(Ordering::Relaxed), 1);
+
+        let entries = queue.entries().unwrap();
+        let attempts = entries.iter().map(|entry| entry.attempts);
+        assert_eq!(attempts.collect::<Vec<_>>(), vec![1, 0, 0]);
+
+        fs::remove_dir_all(queue.dir()).unwrap();
+    }
+}
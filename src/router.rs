@@ -1,20 +1,76 @@
+pub mod access;
 pub mod filters;
 pub mod layers;
+mod pattern;
+
+use std::time::Duration;
 
 use async_trait::async_trait;
+pub use pattern::{ParamError, Params, Pattern};
 
 use crate::service::{
-    response::{Response, ResponseResult},
+    response::{ErrorResponse, Response, ResponseResult},
     Request, Service,
 };
 
 /// Represents a router filter.
 /// A router filter is used by the router to know if the route must be enrouted.
 pub trait Filter: Send + Sync + 'static {
+    /// Check if the request must be enrouted
+    fn check(&self, request: &Request) -> bool;
+
+    /// Parameters captured from a request that passes the filter, given to
+    /// the service of its route, see [`Params::current`].
+    /// By default, none.
+    fn params(&self, _request: &Request) -> Params {
+        Params::default()
+    }
+}
+
+/// A router filter that only needs the header of the request.
+/// Any header filter is a [`Filter`].
+pub trait HeaderFilter: Send + Sync + 'static {
     /// Check if a message with the specified header must be enrouted
-    fn check(&self, header: &str) -> bool;
+    fn check_header(&self, header: &str) -> bool;
+
+    /// Same as [`Filter::params`], from the header.
+    fn header_params(&self, _header: &str) -> Params {
+        Params::default()
+    }
+}
+
+impl<F: HeaderFilter> Filter for F {
+    fn check(&self, request: &Request) -> bool {
+        self.check_header(&request.header)
+    }
+
+    fn params(&self, request: &Request) -> Params {
+        self.header_params(&request.header)
+    }
+}
+
+/// Combines the filters, e.g.
+/// `StartWith("Report").and(StartWith("Report draft").not())`.
+/// See [`filters::And`], [`filters::Or`] and [`filters::Not`].
+pub trait FilterExt: Filter + Sized {
+    /// Matches if both filters match.
+    fn and<F: Filter>(self, other: F) -> filters::And<Self, F> {
+        filters::And(self, other)
+    }
+
+    /// Matches if any of the filters matches.
+    fn or<F: Filter>(self, other: F) -> filters::Or<Self, F> {
+        filters::Or(self, other)
+    }
+
+    /// Matches if the filter does not match.
+    fn not(self) -> filters::Not<Self> {
+        filters::Not(self)
+    }
 }
 
+impl<F: Filter> FilterExt for F {}
+
 /// Represents a router layer.
 /// A router layer performs a mapping of the request/response for any message
 /// before being enrouted.
@@ -24,28 +80,56 @@ pub trait Layer: Send + Sync + 'static {
         request
     }
 
+    /// Answers the request instead of the route, if it returns a response.
+    /// It is checked once all the layers mapped the request, and the response
+    /// is mapped by all of them as the one of a route.
+    /// By default, any request reaches its route.
+    fn intercept(&self, _request: &Request) -> Option<ResponseResult> {
+        None
+    }
+
     /// Maps the response type
     fn map_response(&self, response: ResponseResult) -> ResponseResult {
         response
     }
 }
 
+tokio::task_local! {
+    /// Parameters of the routes processing a request, see [`Params::current`].
+    static PARAMS: Params;
+}
+
 /// Represents a route
 /// A route will be run a services if the filter condition is true.
 pub struct Route<F, S> {
     filter: F,
     service: S,
+    timeout: Option<Duration>,
 }
 
 #[async_trait]
 impl<State, F, S> Service<State> for Route<F, S>
 where
     State: Send + 'static,
-    F: Send + Sync + 'static,
+    F: Filter,
     S: Service<State>,
 {
     async fn call(&self, request: Request, state: State) -> ResponseResult {
-        self.service.call(request, state).await
+        let mut params = Params::current();
+        params.0.extend(self.filter.params(&request).0);
+
+        let call = PARAMS.scope(params, self.service.call(request, state));
+
+        let Some(timeout) = self.timeout else {
+            return call.await;
+        };
+
+        tokio::time::timeout(timeout, call)
+            .await
+            .unwrap_or_else(|_| {
+                log::warn!("Route timed out after {:?}", timeout);
+                Err(ErrorResponse::System(Response::timeout()))
+            })
     }
 }
 
@@ -54,8 +138,8 @@ where
     F: Filter,
     S: Send + Sync + 'static,
 {
-    fn check(&self, header: &str) -> bool {
-        self.filter.check(header)
+    fn check(&self, request: &Request) -> bool {
+        self.filter.check(request)
     }
 }
 
@@ -75,7 +159,43 @@ pub struct Router<State> {
 impl<State: Send + Sync + 'static> Router<State> {
     /// Adds a route to the router.
     pub fn route(mut self, filter: impl Filter, service: impl Service<State>) -> Self {
-        self.routes.push(Box::new(Route { filter, service }));
+        self.routes.push(Box::new(Route {
+            filter,
+            service,
+            timeout: None,
+        }));
+        self
+    }
+
+    /// Adds a route to the router whose service is given up after `timeout`.
+    /// Once given up, the route answers with a system error, that the layers
+    /// of the router can map as any other, e.g. with
+    /// [`layers::ErrorHeader`].
+    pub fn route_with_timeout(
+        mut self,
+        filter: impl Filter,
+        timeout: Duration,
+        service: impl Service<State>,
+    ) -> Self {
+        self.routes.push(Box::new(Route {
+            filter,
+            service,
+            timeout: Some(timeout),
+        }));
+        self
+    }
+
+    /// Adds a router as a route for the headers starting with the words of
+    /// the prefix, e.g. `infra` for `infra deploy api`.
+    /// The prefix is stripped from the header before the routes of the router
+    /// check it, and their services receive it stripped too.
+    /// The layers of the router only process the requests routed to it,
+    /// after the ones of this router.
+    pub fn nest(mut self, prefix: impl Into<String>, router: Router<State>) -> Self {
+        self.routes.push(Box::new(Nested {
+            prefix: prefix.into(),
+            router,
+        }));
         self
     }
 
@@ -87,6 +207,37 @@ impl<State: Send + Sync + 'static> Router<State> {
     }
 }
 
+/// Route of a router added with [`Router::nest`].
+struct Nested<State> {
+    prefix: String,
+    router: Router<State>,
+}
+
+impl<State> Nested<State> {
+    /// The header without the prefix, if it starts with it.
+    fn strip<'a>(&self, header: &'a str) -> Option<&'a str> {
+        let rest = header.strip_prefix(self.prefix.as_str())?;
+        (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim_start())
+    }
+}
+
+impl<State: Send + Sync + 'static> HeaderFilter for Nested<State> {
+    fn check_header(&self, header: &str) -> bool {
+        self.strip(header).is_some()
+    }
+}
+
+#[async_trait]
+impl<State: Send + Sync + 'static> Service<State> for Nested<State> {
+    async fn call(&self, mut request: Request, state: State) -> ResponseResult {
+        if let Some(header) = self.strip(&request.header) {
+            request.header = header.into();
+        }
+
+        self.router.call(request, state).await
+    }
+}
+
 #[async_trait]
 impl<State: Sync + Send + 'static> Service<State> for Router<State> {
     async fn call(&self, request: Request, state: State) -> ResponseResult {
@@ -95,18 +246,133 @@ impl<State: Sync + Send + 'static> Service<State> for Router<State> {
             .iter()
             .fold(request, |request, layer| layer.map_request(request));
 
-        let route = self
-            .routes
+        let intercepted = self
+            .layers
             .iter()
-            .find(|route| route.check(&request.header));
+            .find_map(|layer| layer.intercept(&request));
 
-        let response = match route {
-            Some(route) => route.call(request, state).await,
-            None => Response::none(),
+        let response = match intercepted {
+            Some(response) => response,
+            None => {
+                let route = self.routes.iter().find(|route| route.check(&request));
+
+                match route {
+                    Some(route) => route.call(request, state).await,
+                    None => Response::none(),
+                }
+            }
         };
 
+        self.map_response(response)
+    }
+
+    fn map_response(&self, response: ResponseResult) -> ResponseResult {
         self.layers
             .iter()
             .fold(response, |response, layer| layer.map_response(response))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        router::{filters::Any, layers::ErrorHeader},
+        service::user_error,
+    };
+
+    fn request(header: &str) -> Request {
+        Request {
+            address: "user@domain.com".into(),
+            header: header.into(),
+            body: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    async fn slow(_: Request, _: ()) -> ResponseResult {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Response::ok("Slow", "done")
+    }
+
+    async fn fast(_: Request, _: ()) -> ResponseResult {
+        Response::ok("Fast", "done")
+    }
+
+    #[tokio::test]
+    async fn route_timeout() {
+        let router = Router::default()
+            .route_with_timeout("Slow", Duration::from_millis(10), slow)
+            .route_with_timeout("Fast", Duration::from_secs(60), fast)
+            .layer(ErrorHeader("System error", "User error"));
+
+        match router.call(request("Slow"), ()).await {
+            Err(ErrorResponse::System(response)) => assert_eq!(response.header, "System error"),
+            _ => panic!("expected a system error"),
+        }
+
+        match router.call(request("Fast"), ()).await {
+            Ok(Some(response)) => assert_eq!(response.header, "Fast"),
+            _ => panic!("expected a response"),
+        }
+    }
+
+    async fn sum(_: Request, _: ()) -> ResponseResult {
+        let params = Params::current();
+        let a: i32 = params.parse("a").map_err(user_error)?;
+        let b: i32 = params.parse("b").map_err(user_error)?;
+        Response::ok("Sum", format!("{}", a + b))
+    }
+
+    #[tokio::test]
+    async fn route_params() {
+        let router = Router::default().route(Pattern("Sum {a} {b}"), sum);
+
+        match router.call(request("Sum 1 2"), ()).await {
+            Ok(Some(response)) => assert_eq!(response.body.to_string().trim(), "3"),
+            _ => panic!("expected a response"),
+        }
+
+        match router.call(request("Sum 1 x"), ()).await {
+            Err(ErrorResponse::User(response)) => assert_eq!(
+                response.body.to_string().trim(),
+                "Invalid parameter 'b', 'x': invalid digit found in string"
+            ),
+            _ => panic!("expected a user error"),
+        }
+    }
+
+    async fn echo(req: Request, _: ()) -> ResponseResult {
+        Response::ok(req.header, "")
+    }
+
+    #[tokio::test]
+    async fn nested_routers() {
+        let infra = Router::default()
+            .route(Pattern("deploy {service}"), sum)
+            .route(Any, echo)
+            .layer(ErrorHeader("Infra error", "Infra error"));
+
+        let router = Router::default()
+            .nest("infra", infra)
+            .nest("billing", Router::default().route(Any, echo))
+            .route(Any, echo);
+
+        match router.call(request("infra  deploy api"), ()).await {
+            Err(ErrorResponse::User(response)) => assert_eq!(response.header, "Infra error"),
+            _ => panic!("expected a user error"),
+        }
+
+        for (header, routed) in [
+            ("billing invoices", "invoices"),
+            ("billing", ""),
+            ("billings", "billings"),
+            ("infrastructure", "infrastructure"),
+        ] {
+            match router.call(request(header), ()).await {
+                Ok(Some(response)) => assert_eq!(response.header, routed),
+                _ => panic!("expected a response"),
+            }
+        }
+    }
+}
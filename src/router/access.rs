@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use crate::{
+    router::{pattern::glob, Layer},
+    service::{
+        response::{Response, ResponseResult},
+        Request, Service,
+    },
+};
+
+/// Decides which senders can use a service, by their address.
+///
+/// The addresses are checked against patterns, where `*` matches any text:
+/// `alice@company.com`, `*@company.com` or `*@*.company.com`. A pattern
+/// starting with `@`, like `@company.com`, is a short form for a whole domain.
+/// Patterns are case-insensitive.
+///
+/// A sender is allowed if it matches no denied pattern, and it matches any of
+/// the allowed ones. Without allowed patterns, any sender not denied is
+/// allowed. The rest of the senders are answered with a user error.
+///
+/// It can be used as a [`Layer`] to protect all the routes of a router,
+/// and to protect single routes, optionally to the members of a role:
+/// ```
+/// use mailfred::{
+///     router::{access::Access, Router},
+///     service::{Request, Response, ResponseResult},
+/// };
+///
+/// async fn status(_: Request, _: ()) -> ResponseResult {
+///     Response::ok("Status", "Everything is fine")
+/// }
+///
+/// async fn deploy(_: Request, _: ()) -> ResponseResult {
+///     Response::ok("Deploy", "Deployed")
+/// }
+///
+/// let access = Access::default()
+///     .allow("@company.com")
+///     .deny("intern@company.com")
+///     .role("ops", "alice@company.com");
+///
+/// let router = Router::default()
+///     .route("Status", status)
+///     .route("Deploy", access.require("ops", deploy))
+///     .layer(access);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Access {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+    roles: HashMap<String, Vec<String>>,
+    header: String,
+    body: String,
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Access {
+            allowed: Vec::new(),
+            denied: Vec::new(),
+            roles: HashMap::new(),
+            header: "Not allowed".into(),
+            body: "You are not allowed to make this request.".into(),
+        }
+    }
+}
+
+impl Access {
+    /// Allows the senders matching the pattern.
+    pub fn allow(mut self, pattern: impl AsRef<str>) -> Self {
+        self.allowed.push(normalize(pattern.as_ref()));
+        self
+    }
+
+    /// Denies the senders matching the pattern, even if they are allowed.
+    pub fn deny(mut self, pattern: impl AsRef<str>) -> Self {
+        self.denied.push(normalize(pattern.as_ref()));
+        self
+    }
+
+    /// Adds the senders matching the pattern to a role.
+    /// Only the allowed senders can act as members of a role.
+    pub fn role(mut self, role: impl Into<String>, pattern: impl AsRef<str>) -> Self {
+        self.roles
+            .entry(role.into())
+            .or_default()
+            .push(normalize(pattern.as_ref()));
+        self
+    }
+
+    /// Sets the user error answered to the senders not allowed.
+    pub fn response(mut self, header: impl Into<String>, body: impl Into<String>) -> Self {
+        self.header = header.into();
+        self.body = body.into();
+        self
+    }
+
+    pub fn is_allowed(&self, address: &str) -> bool {
+        let address = address.to_lowercase();
+        let matches = |pattern: &String| glob(pattern, &address);
+
+        !self.denied.iter().any(matches)
+            && (self.allowed.is_empty() || self.allowed.iter().any(matches))
+    }
+
+    pub fn has_role(&self, address: &str, role: &str) -> bool {
+        let address_lowercase = address.to_lowercase();
+
+        self.is_allowed(address)
+            && self.roles.get(role).is_some_and(|patterns| {
+                patterns
+                    .iter()
+                    .any(|pattern| glob(pattern, &address_lowercase))
+            })
+    }
+
+    /// Protects a single service with these rules.
+    pub fn restrict<S>(&self, service: S) -> Restricted<S> {
+        Restricted {
+            access: self.clone(),
+            role: None,
+            service,
+        }
+    }
+
+    /// Protects a single service with these rules,
+    /// only allowing the members of the given role.
+    pub fn require<S>(&self, role: impl Into<String>, service: S) -> Restricted<S> {
+        Restricted {
+            access: self.clone(),
+            role: Some(role.into()),
+            service,
+        }
+    }
+
+    fn check(&self, address: &str, role: Option<&str>) -> Option<ResponseResult> {
+        let allowed = match role {
+            Some(role) => self.has_role(address, role),
+            None => self.is_allowed(address),
+        };
+
+        if allowed {
+            return None;
+        }
+
+        log::info!("Request from '{}' not allowed", address);
+        Some(Response::user_err(self.header.as_str(), self.body.as_str()))
+    }
+}
+
+impl Layer for Access {
+    fn intercept(&self, request: &Request) -> Option<ResponseResult> {
+        self.check(&request.address, None)
+    }
+}
+
+/// A service protected by an [`Access`].
+pub struct Restricted<S> {
+    access: Access,
+    role: Option<String>,
+    service: S,
+}
+
+#[async_trait]
+impl<State, S> Service<State> for Restricted<S>
+where
+    State: Send + 'static,
+    S: Service<State>,
+{
+    async fn call(&self, request: Request, state: State) -> ResponseResult {
+        match self.access.check(&request.address, self.role.as_deref()) {
+            Some(denied) => denied,
+            None => self.service.call(request, state).await,
+        }
+    }
+}
+
+fn normalize(pattern: &str) -> String {
+    match pattern.strip_prefix('@') {
+        Some(domain) => format!("*@{}", domain),
+        None => pattern.into(),
+    }
+    .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allow_and_deny() {
+        let anyone = Access::default();
+        assert!(anyone.is_allowed("a@b.com"));
+
+        let access = Access::default()
+            .allow("@Company.com")
+            .allow("friend@other.com")
+            .deny("intern@company.com");
+
+        assert!(access.is_allowed("alice@company.com"));
+        assert!(access.is_allowed("Friend@Other.com"));
+        assert!(!access.is_allowed("intern@company.com"));
+        assert!(!access.is_allowed("someone@other.com"));
+    }
+
+    #[test]
+    fn roles() {
+        let access = Access::default()
+            .deny("bob@company.com")
+            .role("ops", "alice@company.com")
+            .role("ops", "bob@company.com");
+
+        assert!(access.has_role("alice@company.com", "ops"));
+        assert!(!access.has_role("alice@company.com", "billing"));
+        assert!(!access.has_role("bob@company.com", "ops"));
+        assert!(!access.has_role("carol@company.com", "ops"));
+    }
+}
@@ -1,23 +1,291 @@
-use crate::router::Filter;
+//! Filters of the routes. Most of them match the header of the request, and
+//! [`FromDomain`], [`ToAddress`] and [`HasAttachment`] match the rest of it.
+//!
+//! The patterns can be `&'static str` or owned `String`, so the routes can be
+//! built from a configuration read at runtime.
 
-impl Filter for &'static str {
-    fn check(&self, value: &str) -> bool {
+use crate::{
+    message::Kind,
+    router::{pattern::glob, Filter, HeaderFilter, Params},
+    service::Request,
+};
+
+impl HeaderFilter for &'static str {
+    fn check_header(&self, value: &str) -> bool {
+        *self == value
+    }
+}
+
+impl HeaderFilter for String {
+    fn check_header(&self, value: &str) -> bool {
         *self == value
     }
 }
 
 pub struct Any;
 
-impl Filter for Any {
-    fn check(&self, _: &str) -> bool {
+impl HeaderFilter for Any {
+    fn check_header(&self, _: &str) -> bool {
         true
     }
 }
 
-pub struct StartWith(pub &'static str);
+pub struct StartWith<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for StartWith<T> {
+    fn check_header(&self, header: &str) -> bool {
+        header.starts_with(self.0.as_ref())
+    }
+}
+
+pub struct EndsWith<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for EndsWith<T> {
+    fn check_header(&self, header: &str) -> bool {
+        header.ends_with(self.0.as_ref())
+    }
+}
+
+pub struct Contains<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for Contains<T> {
+    fn check_header(&self, header: &str) -> bool {
+        header.contains(self.0.as_ref())
+    }
+}
+
+/// Matches the whole header, where `*` matches any text,
+/// e.g. `Report * of *`.
+pub struct Glob<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for Glob<T> {
+    fn check_header(&self, header: &str) -> bool {
+        glob(self.0.as_ref(), header)
+    }
+}
+
+/// Same as an exact `&str`, ignoring the case.
+pub struct IgnoreCase<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for IgnoreCase<T> {
+    fn check_header(&self, header: &str) -> bool {
+        header.to_lowercase() == self.0.as_ref().to_lowercase()
+    }
+}
+
+/// Same as [`StartWith`], ignoring the case.
+pub struct StartWithIgnoreCase<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for StartWithIgnoreCase<T> {
+    fn check_header(&self, header: &str) -> bool {
+        header
+            .to_lowercase()
+            .starts_with(&self.0.as_ref().to_lowercase())
+    }
+}
+
+/// Same as [`EndsWith`], ignoring the case.
+pub struct EndsWithIgnoreCase<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for EndsWithIgnoreCase<T> {
+    fn check_header(&self, header: &str) -> bool {
+        header
+            .to_lowercase()
+            .ends_with(&self.0.as_ref().to_lowercase())
+    }
+}
+
+/// Same as [`Contains`], ignoring the case.
+pub struct ContainsIgnoreCase<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for ContainsIgnoreCase<T> {
+    fn check_header(&self, header: &str) -> bool {
+        header
+            .to_lowercase()
+            .contains(&self.0.as_ref().to_lowercase())
+    }
+}
+
+/// Same as [`Glob`], ignoring the case.
+pub struct GlobIgnoreCase<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for GlobIgnoreCase<T> {
+    fn check_header(&self, header: &str) -> bool {
+        glob(&self.0.as_ref().to_lowercase(), &header.to_lowercase())
+    }
+}
+
+pub struct And<A, B>(pub A, pub B);
+
+impl<A: Filter, B: Filter> Filter for And<A, B> {
+    fn check(&self, request: &Request) -> bool {
+        self.0.check(request) && self.1.check(request)
+    }
+
+    fn params(&self, request: &Request) -> Params {
+        let mut params = self.0.params(request);
+        params.0.extend(self.1.params(request).0);
+        params
+    }
+}
+
+pub struct Or<A, B>(pub A, pub B);
+
+impl<A: Filter, B: Filter> Filter for Or<A, B> {
+    fn check(&self, request: &Request) -> bool {
+        self.0.check(request) || self.1.check(request)
+    }
+
+    fn params(&self, request: &Request) -> Params {
+        match self.0.check(request) {
+            true => self.0.params(request),
+            false => self.1.params(request),
+        }
+    }
+}
+
+pub struct Not<F>(pub F);
+
+impl<F: Filter> Filter for Not<F> {
+    fn check(&self, request: &Request) -> bool {
+        !self.0.check(request)
+    }
+}
+
+/// Matches the requests sent from an address of the domain, ignoring the
+/// case. Its subdomains are other domains.
+pub struct FromDomain<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> Filter for FromDomain<T> {
+    fn check(&self, request: &Request) -> bool {
+        request
+            .address
+            .rsplit_once('@')
+            .is_some_and(|(_, domain)| domain.eq_ignore_ascii_case(self.0.as_ref()))
+    }
+}
+
+/// Matches the requests sent to an address, ignoring the case, where `*`
+/// matches any text, e.g. `bot+*@company.com`.
+/// See [`crate::message::Message::recipients`].
+pub struct ToAddress<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> Filter for ToAddress<T> {
+    fn check(&self, request: &Request) -> bool {
+        let pattern = self.0.as_ref().to_lowercase();
+        request
+            .recipients
+            .iter()
+            .any(|address| glob(&pattern, &address.to_lowercase()))
+    }
+}
+
+/// Matches the requests with an attachment whose name matches the pattern,
+/// ignoring the case, where `*` matches any text, e.g. `*.csv`.
+pub struct HasAttachment<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> Filter for HasAttachment<T> {
+    fn check(&self, request: &Request) -> bool {
+        let pattern = self.0.as_ref().to_lowercase();
+        request.body.iter().any(|part| match &part.kind {
+            Kind::Attachment(name) => glob(&pattern, &name.to_lowercase()),
+            _ => false,
+        })
+    }
+}
+
+/// Matches the header with a regular expression. As any other regular
+/// expression, it matches anywhere in the header unless anchored with `^` and
+/// `$`, and ignores the case with `(?i)`.
+#[cfg(feature = "regex")]
+pub struct Regex(pub regex::Regex);
+
+#[cfg(feature = "regex")]
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        regex::Regex::new(pattern).map(Regex)
+    }
+}
+
+#[cfg(feature = "regex")]
+impl HeaderFilter for Regex {
+    fn check_header(&self, header: &str) -> bool {
+        self.0.is_match(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::Part, router::FilterExt};
+
+    fn request(header: &str) -> Request {
+        Request {
+            address: "user@Company.com".into(),
+            header: header.into(),
+            body: vec![Part {
+                kind: Kind::Attachment("Data.CSV".into()),
+                content: Vec::new(),
+            }],
+            recipients: vec!["bot@company.com".into(), "bot+billing@company.com".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        assert!("Sum".check_header("Sum"));
+        assert!(!"Sum".check_header("sum"));
+        assert!(String::from("Sum").check_header("Sum"));
+        assert!(StartWith("Report").check_header("Report 3"));
+        assert!(StartWith(String::from("Report")).check_header("Report 3"));
+        assert!(EndsWith(".csv").check_header("data.csv"));
+        assert!(!EndsWith(".csv").check_header("data.CSV"));
+        assert!(Contains("urgent").check_header("an urgent task"));
+        assert!(Glob("Report * of *").check_header("Report 3 of 2024"));
+        assert!(!Glob("Report * of *").check_header("Report 3"));
+    }
+
+    #[test]
+    fn patterns_ignoring_case() {
+        assert!(IgnoreCase("Sum").check_header("SUM"));
+        assert!(StartWithIgnoreCase("report").check_header("REPORT 3"));
+        assert!(EndsWithIgnoreCase(".csv").check_header("data.CSV"));
+        assert!(ContainsIgnoreCase("Urgent").check_header("an URGENT task"));
+        assert!(GlobIgnoreCase("report * of *").check_header("Report 3 OF 2024"));
+        assert!(!GlobIgnoreCase("report * of *").check_header("Report 3"));
+    }
+
+    #[test]
+    fn combinators() {
+        let reports = StartWith("Report").and(StartWith("Report draft").not());
+        assert!(reports.check(&request("Report 3")));
+        assert!(!reports.check(&request("Report draft 3")));
+        assert!(!reports.check(&request("Sum")));
+
+        let sums = "Sum".or(IgnoreCase("Add"));
+        assert!(sums.check(&request("Sum")));
+        assert!(sums.check(&request("ADD")));
+        assert!(!sums.check(&request("Sub")));
+    }
+
+    #[test]
+    fn request_filters() {
+        let request = request("Sum");
+        assert!(FromDomain("company.com").check(&request));
+        assert!(!FromDomain("other.com").check(&request));
+        assert!(ToAddress("bot+*@company.com").check(&request));
+        assert!(!ToAddress("sales@company.com").check(&request));
+        assert!(HasAttachment("*.csv").check(&request));
+        assert!(!HasAttachment("*.json").check(&request));
+        assert!("Sum".and(HasAttachment("*.csv")).check(&request));
+    }
 
-impl Filter for StartWith {
-    fn check(&self, header: &str) -> bool {
-        header.starts_with(self.0)
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex() {
+        let filter = Regex::new(r"(?i)^deploy \w+$").unwrap();
+        assert!(filter.check_header("Deploy api"));
+        assert!(!filter.check_header("Deploy api now"));
     }
 }
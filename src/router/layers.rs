@@ -1,3 +1,9 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
 use crate::{
     router::Layer,
     service::{
@@ -36,3 +42,295 @@ impl Layer for ErrorHeader {
         })
     }
 }
+
+/// Part of the address a [`RateLimit`] counts the requests by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateKey {
+    /// The whole address
+    Address,
+    /// The domain of the address, for all its users at once
+    Domain,
+}
+
+impl RateKey {
+    fn of(self, address: &str) -> String {
+        let key = match self {
+            RateKey::Address => address,
+            RateKey::Domain => address
+                .rsplit_once('@')
+                .map_or(address, |(_, domain)| domain),
+        };
+        key.to_lowercase()
+    }
+}
+
+/// Accepts at most `max` requests from the same address, or domain, every
+/// `window` of time. The requests beyond are answered with a user error.
+///
+/// It limits all the routes of its router. To limit a single route, give it
+/// a router of its own:
+/// ```
+/// use std::time::Duration;
+///
+/// use mailfred::{
+///     router::{filters::Any, layers::RateLimit, Router},
+///     service::{Request, Response, ResponseResult},
+/// };
+///
+/// async fn report(_: Request, _: ()) -> ResponseResult {
+///     Response::ok("Report", "Expensive to build")
+/// }
+///
+/// let limited = Router::default()
+///     .route(Any, report)
+///     .layer(RateLimit::per_address(3, Duration::from_secs(3600)));
+///
+/// let router = Router::default().route("Report", limited);
+/// ```
+pub struct RateLimit {
+    max: u32,
+    window: Duration,
+    key: RateKey,
+    header: String,
+    body: String,
+    counts: Mutex<Counts>,
+}
+
+struct Counts {
+    /// Start of the current window of each key, and the requests counted in it
+    windows: HashMap<String, (Instant, u32)>,
+    /// Last time the keys with an expired window were removed
+    pruned: Instant,
+}
+
+impl RateLimit {
+    pub fn new(key: RateKey, max: u32, window: Duration) -> RateLimit {
+        RateLimit {
+            max,
+            window,
+            key,
+            header: "Too many requests".into(),
+            body: "Too many requests were received from you. Try again later.".into(),
+            counts: Mutex::new(Counts {
+                windows: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn per_address(max: u32, window: Duration) -> RateLimit {
+        RateLimit::new(RateKey::Address, max, window)
+    }
+
+    pub fn per_domain(max: u32, window: Duration) -> RateLimit {
+        RateLimit::new(RateKey::Domain, max, window)
+    }
+
+    /// Sets the user error answered once the limit is exceeded.
+    pub fn response(mut self, header: impl Into<String>, body: impl Into<String>) -> Self {
+        self.header = header.into();
+        self.body = body.into();
+        self
+    }
+}
+
+impl Layer for RateLimit {
+    fn intercept(&self, request: &Request) -> Option<ResponseResult> {
+        let now = Instant::now();
+        let mut counts = self.counts.lock().unwrap();
+
+        // Once a window, the keys not seen in it are forgotten
+        if now.duration_since(counts.pruned) >= self.window {
+            let window = self.window;
+            counts
+                .windows
+                .retain(|_, (start, _)| now.duration_since(*start) < window);
+            counts.pruned = now;
+        }
+
+        let (start, count) = counts
+            .windows
+            .entry(self.key.of(&request.address))
+            .or_insert((now, 0));
+
+        // A key whose window expired counts from zero again
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+
+        if *count >= self.max {
+            log::info!("Rate limit exceeded by '{}'", request.address);
+            return Some(Response::user_err(self.header.as_str(), self.body.as_str()));
+        }
+
+        *count += 1;
+        None
+    }
+}
+
+/// Answers with a user error the requests whose sender is not authenticated:
+/// neither DMARC, DKIM nor SPF pass for the domain of its address.
+/// See [`crate::authentication::AuthenticationResults::authenticates`].
+///
+/// Only the results written by the trusted servers are read, by their
+/// `authserv-id` (RFC 8601, section 5). Any other `Authentication-Results`
+/// header could have been written by the sender itself.
+pub struct Authenticated {
+    servers: Vec<String>,
+    header: String,
+    body: String,
+}
+
+impl Authenticated {
+    /// Trusts the results written by a server, e.g. `mx.google.com`, or
+    /// [`crate::authentication::AUTHSERV_ID`] for the DKIM results verified by
+    /// mailfred itself.
+    pub fn new(authserv_id: impl Into<String>) -> Authenticated {
+        Authenticated {
+            servers: vec![authserv_id.into()],
+            header: "Not authenticated".into(),
+            body: "Your message could not be authenticated as sent by your address.".into(),
+        }
+    }
+
+    /// Trusts the results written by another server.
+    pub fn trust(mut self, authserv_id: impl Into<String>) -> Self {
+        self.servers.push(authserv_id.into());
+        self
+    }
+
+    /// Sets the user error answered to the senders not authenticated.
+    pub fn response(mut self, header: impl Into<String>, body: impl Into<String>) -> Self {
+        self.header = header.into();
+        self.body = body.into();
+        self
+    }
+}
+
+impl Layer for Authenticated {
+    fn intercept(&self, request: &Request) -> Option<ResponseResult> {
+        let authenticated = request.authentication.iter().any(|results| {
+            let trusted = self
+                .servers
+                .iter()
+                .any(|server| server.eq_ignore_ascii_case(&results.authserv_id));
+
+            trusted && results.authenticates(&request.address)
+        });
+
+        if authenticated {
+            return None;
+        }
+
+        log::info!("Request from '{}' not authenticated", request.address);
+        Some(Response::user_err(self.header.as_str(), self.body.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authentication::AuthenticationResults;
+
+    fn request(address: &str) -> Request {
+        Request {
+            address: address.into(),
+            header: "".into(),
+            body: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    fn limited(layer: &RateLimit, address: &str) -> bool {
+        layer.intercept(&request(address)).is_some()
+    }
+
+    #[test]
+    fn limit_per_address() {
+        let layer = RateLimit::per_address(2, Duration::from_secs(60));
+
+        assert!(!limited(&layer, "a@b.com"));
+        assert!(!limited(&layer, "A@b.com"));
+        assert!(limited(&layer, "a@b.com"));
+        assert!(!limited(&layer, "c@b.com"));
+    }
+
+    #[test]
+    fn limit_per_domain() {
+        let layer = RateLimit::per_domain(1, Duration::from_secs(60));
+
+        assert!(!limited(&layer, "a@b.com"));
+        assert!(limited(&layer, "c@b.com"));
+        assert!(!limited(&layer, "a@d.com"));
+    }
+
+    #[test]
+    fn window_expires() {
+        let layer = RateLimit::per_address(1, Duration::from_millis(10));
+
+        assert!(!limited(&layer, "a@b.com"));
+        assert!(limited(&layer, "a@b.com"));
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!limited(&layer, "a@b.com"));
+        assert!(limited(&layer, "a@b.com"));
+
+        // The keys not seen in a window are forgotten
+        assert!(!limited(&layer, "c@b.com"));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!limited(&layer, "a@b.com"));
+        assert_eq!(layer.counts.lock().unwrap().windows.len(), 1);
+    }
+
+    #[test]
+    fn authenticated_senders() {
+        let authenticated = |layer: &Authenticated, headers: &[&str]| {
+            let request = Request {
+                authentication: headers
+                    .iter()
+                    .filter_map(|header| AuthenticationResults::parse(header))
+                    .collect(),
+                ..request("a@b.com")
+            };
+            layer.intercept(&request).is_none()
+        };
+
+        let layer = Authenticated::new("mx.c.com");
+        assert!(authenticated(
+            &layer,
+            &["mx.c.com; dkim=pass header.d=b.com"]
+        ));
+        assert!(!authenticated(
+            &layer,
+            &["mx.c.com; dkim=pass header.d=evil.com"]
+        ));
+        assert!(!authenticated(&layer, &[]));
+
+        // The results of other servers are not read, wherever they are
+        assert!(!authenticated(
+            &layer,
+            &["evil.com; spf=pass smtp.mailfrom=b.com"]
+        ));
+        assert!(!authenticated(
+            &layer,
+            &[
+                "evil.com; spf=pass smtp.mailfrom=b.com",
+                "mx.c.com; spf=fail smtp.mailfrom=b.com",
+            ]
+        ));
+        assert!(authenticated(
+            &layer,
+            &[
+                "evil.com; spf=fail smtp.mailfrom=b.com",
+                "mx.c.com; spf=pass smtp.mailfrom=b.com",
+            ]
+        ));
+
+        let layer = Authenticated::new("mx.c.com").trust("mailfred");
+        assert!(authenticated(
+            &layer,
+            &["mailfred; dkim=pass header.d=b.com"]
+        ));
+    }
+}
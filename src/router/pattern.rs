@@ -0,0 +1,200 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use crate::router::HeaderFilter;
+
+/// Matches the words of the header with the ones of the pattern, where a word
+/// written as `{name}` or `<name>` is a parameter matching any word, e.g.
+/// `Sum {a} {b}` or `deploy <service> <env>`.
+/// The service of its route finds the words of its parameters with
+/// [`Params::current`].
+pub struct Pattern<T = &'static str>(pub T);
+
+impl<T: AsRef<str> + Send + Sync + 'static> HeaderFilter for Pattern<T> {
+    fn check_header(&self, header: &str) -> bool {
+        self.captures(header).is_some()
+    }
+
+    fn header_params(&self, header: &str) -> Params {
+        self.captures(header).unwrap_or_default()
+    }
+}
+
+impl<T: AsRef<str>> Pattern<T> {
+    /// Parameters of the header, if it matches the pattern.
+    pub fn captures(&self, header: &str) -> Option<Params> {
+        let pattern = self.0.as_ref().split_whitespace();
+        let mut words = header.split_whitespace();
+
+        let mut params = Params::default();
+        for expected in pattern {
+            let word = words.next()?;
+            match parameter(expected) {
+                Some(name) => params.0.push((name.into(), word.into())),
+                None if expected == word => (),
+                None => return None,
+            }
+        }
+
+        words.next().is_none().then_some(params)
+    }
+}
+
+fn parameter(word: &str) -> Option<&str> {
+    word.strip_prefix('{')
+        .and_then(|word| word.strip_suffix('}'))
+        .or_else(|| word.strip_prefix('<')?.strip_suffix('>'))
+        .filter(|name| !name.is_empty())
+}
+
+/// Parameters captured from the header by the filter of a route,
+/// in the order they are found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params(pub Vec<(String, String)>);
+
+impl Params {
+    /// The parameters captured by the filters of the routes processing the
+    /// current request, from the outermost router. They are only known by the
+    /// service of the route and by what it awaits, not by the tasks it spawns.
+    /// Outside of a route, there are none.
+    pub fn current() -> Params {
+        super::PARAMS.try_with(Params::clone).unwrap_or_default()
+    }
+
+    /// The value of a parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of a parameter, parsed. The error can be answered as is to
+    /// the sender with [`crate::service::user_error`].
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, ParamError>
+    where
+        T::Err: Display,
+    {
+        let value = self
+            .get(name)
+            .ok_or_else(|| ParamError::Missing(name.into()))?;
+
+        value.parse().map_err(|err: T::Err| ParamError::Invalid {
+            name: name.into(),
+            value: value.into(),
+            reason: err.to_string(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// A parameter that can not be read from [`Params`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    Missing(String),
+    Invalid {
+        name: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "Missing parameter '{}'", name),
+            ParamError::Invalid {
+                name,
+                value,
+                reason,
+            } => write!(f, "Invalid parameter '{}', '{}': {}", name, value, reason),
+        }
+    }
+}
+
+impl Error for ParamError {}
+
+/// Tells if the text matches the pattern, where `*` matches any text.
+pub(crate) fn glob(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+
+    // Position of the last `*` seen, and of the text when it was seen
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // The last `*` takes one more character
+            backtrack = Some((star, matched + 1));
+            p = star + 1;
+            t = matched + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob("a@b.com", "a@b.com"));
+        assert!(!glob("a@b.com", "aa@b.com"));
+        assert!(glob("*@b.com", "a@b.com"));
+        assert!(!glob("*@b.com", "a@bb.com"));
+        assert!(glob("*@*.b.com", "a@mail.b.com"));
+        assert!(!glob("*@*.b.com", "a@b.com"));
+        assert!(glob("*", ""));
+        assert!(glob("a*b*c", "aXbYbZc"));
+    }
+
+    #[test]
+    fn captures() {
+        let sum = Pattern("Sum {a} {b}");
+        let params = sum.captures("Sum 1  2").unwrap();
+        assert_eq!(params.get("a"), Some("1"));
+        assert_eq!(params.parse::<i32>("b"), Ok(2));
+        assert_eq!(
+            params.parse::<i32>("c"),
+            Err(ParamError::Missing("c".into()))
+        );
+        assert!(matches!(
+            sum.captures("Sum 1 x").unwrap().parse::<i32>("b"),
+            Err(ParamError::Invalid { .. })
+        ));
+
+        assert!(!sum.check_header("Sum 1"));
+        assert!(!sum.check_header("Sum 1 2 3"));
+        assert!(!sum.check_header("Add 1 2"));
+
+        let deploy = Pattern(String::from("deploy <service> <env>"));
+        let params = deploy.captures("deploy api prod").unwrap();
+        assert_eq!(
+            params.iter().collect::<Vec<_>>(),
+            [("service", "api"), ("env", "prod")]
+        );
+        assert!(Pattern("Count").captures("Count").unwrap().is_empty());
+    }
+}
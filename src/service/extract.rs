@@ -0,0 +1,445 @@
+//! Extractors: the arguments of a handler, read from the request.
+//!
+//! A handler is an async function taking any number of extractors, turned into
+//! a [`Service`] with [`handler`]. When an extractor can not be read, the
+//! handler is not called, and its rejection is answered as a user error.
+//!
+//! ```
+//! use mailfred::{
+//!     router::{Pattern, Router},
+//!     service::{
+//!         extract::{Attachment, Params, Subject},
+//!         handler, Response, ResponseResult,
+//!     },
+//! };
+//!
+//! async fn sum(Params((a, b)): Params<(i32, i32)>) -> ResponseResult {
+//!     Response::ok("Sum", format!("{}", a + b))
+//! }
+//!
+//! async fn size(Subject(subject): Subject, attachment: Attachment) -> ResponseResult {
+//!     Response::ok(subject, format!("{} bytes", attachment.content.len()))
+//! }
+//!
+//! let router: Router<()> = Router::default()
+//!     .route(Pattern("Sum {a} {b}"), handler(sum))
+//!     .route("Size", handler(size));
+//! ```
+//!
+//! `From` is named after the header of the sender, so it is better imported
+//! qualified, as `extract::From`, to not hide [`std::convert::From`].
+
+use std::{future::Future, marker::PhantomData, str::FromStr};
+
+use async_trait::async_trait;
+#[cfg(any(feature = "json", feature = "csv", feature = "toml"))]
+use serde::de::DeserializeOwned;
+
+use crate::{
+    message::Kind,
+    router::{self, ParamError},
+    service::{ErrorResponse, Request, Response, ResponseResult, Service},
+};
+
+/// Reads a value from the request, or the reply to send instead.
+pub trait FromRequest<S>: Sized {
+    /// The rejection is answered to the sender as a user error.
+    fn from_request(request: &Request, state: &S) -> Result<Self, Response>;
+}
+
+/// The whole request.
+impl<S> FromRequest<S> for Request {
+    fn from_request(request: &Request, _: &S) -> Result<Self, Response> {
+        Ok(request.clone())
+    }
+}
+
+/// Never rejected: `None` instead.
+impl<S, T: FromRequest<S>> FromRequest<S> for Option<T> {
+    fn from_request(request: &Request, state: &S) -> Result<Self, Response> {
+        Ok(T::from_request(request, state).ok())
+    }
+}
+
+/// The address of the sender.
+pub struct From(pub String);
+
+impl<S> FromRequest<S> for From {
+    fn from_request(request: &Request, _: &S) -> Result<Self, Response> {
+        Ok(From(request.address.clone()))
+    }
+}
+
+/// The header of the request.
+pub struct Subject(pub String);
+
+impl<S> FromRequest<S> for Subject {
+    fn from_request(request: &Request, _: &S) -> Result<Self, Response> {
+        Ok(Subject(request.header.clone()))
+    }
+}
+
+/// The state given to [`crate::serve`].
+pub struct State<S>(pub S);
+
+impl<S: Clone> FromRequest<S> for State<S> {
+    fn from_request(_: &Request, state: &S) -> Result<Self, Response> {
+        Ok(State(state.clone()))
+    }
+}
+
+/// The first attachment of the request.
+/// Rejected if there is none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+impl<S> FromRequest<S> for Attachment {
+    fn from_request(request: &Request, state: &S) -> Result<Self, Response> {
+        let Attachments(attachments) = Attachments::from_request(request, state)?;
+        attachments
+            .into_iter()
+            .next()
+            .ok_or_else(|| "Expected an attachment".into())
+    }
+}
+
+/// All the attachments of the request, that can be none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachments(pub Vec<Attachment>);
+
+impl<S> FromRequest<S> for Attachments {
+    fn from_request(request: &Request, _: &S) -> Result<Self, Response> {
+        let attachments = request.body.iter().filter_map(|part| match &part.kind {
+            Kind::Attachment(name) => Some(Attachment {
+                name: name.clone(),
+                content: part.content.clone(),
+            }),
+            _ => None,
+        });
+
+        Ok(Attachments(attachments.collect()))
+    }
+}
+
+/// The text parts of the request, one after the other.
+/// Rejected if there is none, or if they are not UTF-8.
+pub struct TextBody(pub String);
+
+impl<S> FromRequest<S> for TextBody {
+    fn from_request(request: &Request, _: &S) -> Result<Self, Response> {
+        let mut parts = request.text_iter().peekable();
+        if parts.peek().is_none() {
+            return Err("Expected a text body".into());
+        }
+
+        parts
+            .map(|part| part.as_utf8())
+            .collect::<Result<String, _>>()
+            .map(TextBody)
+            .map_err(|_| "The text body is not valid UTF-8".into())
+    }
+}
+
+/// The parameters captured by the filter of the route, see
+/// [`router::Params::current`], parsed in their order into a tuple, e.g.
+/// `Params<(i32, String)>`. A single one is still a tuple: `Params<(i32,)>`.
+/// To read them by name, [`router::Params`] is an extractor too.
+pub struct Params<T>(pub T);
+
+impl<S, T: FromParams> FromRequest<S> for Params<T> {
+    fn from_request(_: &Request, _: &S) -> Result<Self, Response> {
+        T::from_params(&router::Params::current())
+            .map(Params)
+            .map_err(Into::into)
+    }
+}
+
+impl<S> FromRequest<S> for router::Params {
+    fn from_request(_: &Request, _: &S) -> Result<Self, Response> {
+        Ok(router::Params::current())
+    }
+}
+
+/// A tuple read from the parameters of a request, see [`Params`].
+pub trait FromParams: Sized {
+    fn from_params(params: &router::Params) -> Result<Self, ParamError>;
+}
+
+macro_rules! from_params {
+    ($($ty:ident),+) => {
+        impl<$($ty),+> FromParams for ($($ty,)+)
+        where
+            $($ty: FromStr, $ty::Err: std::fmt::Display,)+
+        {
+            fn from_params(params: &router::Params) -> Result<Self, ParamError> {
+                // Missing ones are named by their position
+                let mut names = params.iter().map(|(name, _)| name);
+                let mut position = 0;
+                Ok(($(
+                    {
+                        position += 1;
+                        let name = names
+                            .next()
+                            .ok_or_else(|| ParamError::Missing(position.to_string()))?;
+                        params.parse::<$ty>(name)?
+                    },
+                )+))
+            }
+        }
+    };
+}
+
+from_params!(T1);
+from_params!(T1, T2);
+from_params!(T1, T2, T3);
+from_params!(T1, T2, T3, T4);
+from_params!(T1, T2, T3, T4, T5);
+from_params!(T1, T2, T3, T4, T5, T6);
+
+/// The first attachment named `*.json`, or else the text body, deserialized.
+#[cfg(feature = "json")]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<S, T: DeserializeOwned> FromRequest<S> for Json<T> {
+    fn from_request(request: &Request, _: &S) -> Result<Self, Response> {
+        serde_json::from_slice(document(request, "json", "JSON")?)
+            .map(Json)
+            .map_err(|err| format!("Invalid JSON: {}", err).into())
+    }
+}
+
+/// The first attachment named `*.csv`, or else the text body, whose records are
+/// deserialized by the names of its first row, e.g. `Csv<Vec<Record>>`.
+#[cfg(feature = "csv")]
+pub struct Csv<T>(pub T);
+
+#[cfg(feature = "csv")]
+impl<S, T: DeserializeOwned> FromRequest<S> for Csv<Vec<T>> {
+    fn from_request(request: &Request, _: &S) -> Result<Self, Response> {
+        csv::Reader::from_reader(document(request, "csv", "CSV")?)
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map(Csv)
+            .map_err(|err| format!("Invalid CSV: {}", err).into())
+    }
+}
+
+/// The first attachment named `*.toml`, or else the text body, deserialized.
+#[cfg(feature = "toml")]
+pub struct Toml<T>(pub T);
+
+#[cfg(feature = "toml")]
+impl<S, T: DeserializeOwned> FromRequest<S> for Toml<T> {
+    fn from_request(request: &Request, _: &S) -> Result<Self, Response> {
+        std::str::from_utf8(document(request, "toml", "TOML")?)
+            .map_err(|err| err.to_string())
+            .and_then(|document| toml::from_str(document).map_err(|err| err.to_string()))
+            .map(Toml)
+            .map_err(|err| format!("Invalid TOML: {}", err).into())
+    }
+}
+
+/// The document read by the extractors of a format. The extension of the
+/// attachment is matched ignoring the case, and only the first text part is
+/// read.
+#[cfg(any(feature = "json", feature = "csv", feature = "toml"))]
+fn document<'a>(request: &'a Request, extension: &str, format: &str) -> Result<&'a [u8], Response> {
+    let extension = format!(".{}", extension);
+    request
+        .attachment_iter()
+        .find(|part| {
+            part.kind
+                .attachment_name()
+                .to_lowercase()
+                .ends_with(&extension)
+        })
+        .or_else(|| request.text_iter().next())
+        .map(|part| part.content.as_slice())
+        .ok_or_else(|| format!("Expected a {} attachment or text body", format).into())
+}
+
+/// An async function whose arguments are extractors.
+/// `T` is the tuple of their types.
+#[async_trait]
+pub trait Handler<T, S>: Send + Sync + 'static {
+    async fn call(&self, request: Request, state: S) -> ResponseResult;
+}
+
+macro_rules! handler {
+    ($($ty:ident),*) => {
+        #[async_trait]
+        #[allow(non_snake_case, unused_variables)]
+        impl<F, Fut, S, $($ty),*> Handler<($($ty,)*), S> for F
+        where
+            F: Fn($($ty),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = ResponseResult> + Send,
+            S: Send + 'static,
+            $($ty: FromRequest<S> + Send,)*
+        {
+            async fn call(&self, request: Request, state: S) -> ResponseResult {
+                $(
+                    let $ty = $ty::from_request(&request, &state).map_err(ErrorResponse::User)?;
+                )*
+                (self)($($ty),*).await
+            }
+        }
+    };
+}
+
+handler!();
+handler!(T1);
+handler!(T1, T2);
+handler!(T1, T2, T3);
+handler!(T1, T2, T3, T4);
+handler!(T1, T2, T3, T4, T5);
+handler!(T1, T2, T3, T4, T5, T6);
+handler!(T1, T2, T3, T4, T5, T6, T7);
+handler!(T1, T2, T3, T4, T5, T6, T7, T8);
+
+/// The service of a handler, built with [`handler`].
+pub struct HandlerService<H, T, S> {
+    handler: H,
+    extractors: PhantomData<fn() -> (T, S)>,
+}
+
+/// Turns a handler into a service, to be given to a route or to
+/// [`crate::serve`].
+pub fn handler<H: Handler<T, S>, T, S>(handler: H) -> HandlerService<H, T, S> {
+    HandlerService {
+        handler,
+        extractors: PhantomData,
+    }
+}
+
+#[async_trait]
+impl<H, T, S> Service<S> for HandlerService<H, T, S>
+where
+    H: Handler<T, S>,
+    T: 'static,
+    S: Send + 'static,
+{
+    async fn call(&self, request: Request, state: S) -> ResponseResult {
+        self.handler.call(request, state).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::Part,
+        router::{Pattern, Router},
+    };
+
+    fn request(header: &str, body: Vec<Part>) -> Request {
+        Request {
+            address: "user@domain.com".into(),
+            header: header.into(),
+            body,
+            ..Default::default()
+        }
+    }
+
+    async fn reply(service: &impl Service<u32>, request: Request) -> (bool, String) {
+        match service.call(request, 3).await {
+            Ok(Some(response)) => (true, response.body.to_string().trim().into()),
+            Err(ErrorResponse::User(response)) => (false, response.body.to_string().trim().into()),
+            _ => panic!("expected a response or a user error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn extractors() {
+        async fn sum(
+            From(address): From,
+            State(state): State<u32>,
+            Params((a, b)): Params<(u32, u32)>,
+        ) -> ResponseResult {
+            Response::ok("Sum", format!("{} {}", address, a + b + state))
+        }
+
+        // The parameters are captured by the route
+        let sum = Router::default().route(Pattern("Sum {a} {b}"), handler(sum));
+        let (ok, body) = reply(&sum, request("Sum 1 2", Vec::new())).await;
+        assert!(ok);
+        assert_eq!(body, "user@domain.com 6");
+
+        let (ok, body) = reply(&sum, request("Sum 1 x", Vec::new())).await;
+        assert!(!ok);
+        assert_eq!(
+            body,
+            "Invalid parameter 'b', 'x': invalid digit found in string"
+        );
+    }
+
+    #[tokio::test]
+    async fn body_extractors() {
+        async fn size(attachment: Attachment, TextBody(text): TextBody) -> ResponseResult {
+            Response::ok("Size", format!("{} {}", attachment.name, text))
+        }
+
+        let size = handler(size);
+        let body = vec![
+            Part {
+                kind: Kind::Text,
+                content: "Hello".into(),
+            },
+            Part {
+                kind: Kind::Attachment("data.csv".into()),
+                content: "1,2".into(),
+            },
+        ];
+        let (ok, body) = reply(&size, request("Size", body)).await;
+        assert!(ok);
+        assert_eq!(body, "data.csv Hello");
+
+        let (ok, body) = reply(&size, request("Size", Vec::new())).await;
+        assert!(!ok);
+        assert_eq!(body, "Expected an attachment");
+    }
+
+    #[cfg(all(feature = "json", feature = "csv", feature = "toml"))]
+    #[tokio::test]
+    async fn document_extractors() {
+        #[derive(serde::Deserialize)]
+        struct Record {
+            value: u32,
+        }
+
+        async fn sum(
+            Json(json): Json<Vec<u32>>,
+            Csv(csv): Csv<Vec<Record>>,
+            Toml(toml): Toml<Record>,
+        ) -> ResponseResult {
+            let csv = csv.iter().map(|record| record.value).sum::<u32>();
+            let json = json.iter().sum::<u32>();
+            Response::ok("Sum", format!("{} {} {}", json, csv, toml.value))
+        }
+
+        let sum = handler(sum);
+        let attachment = |name: &str, content: &str| Part {
+            kind: Kind::Attachment(name.into()),
+            content: content.into(),
+        };
+        let body = vec![
+            Part {
+                kind: Kind::Text,
+                content: "[1, 2]".into(),
+            },
+            attachment("values.CSV", "value\n3\n4\n"),
+            attachment("record.toml", "value = 5"),
+        ];
+        let (ok, body) = reply(&sum, request("Sum", body)).await;
+        assert!(ok);
+        assert_eq!(body, "3 7 5");
+
+        let body = vec![attachment("values.json", "[1,")];
+        let (ok, body) = reply(&sum, request("Sum", body)).await;
+        assert!(!ok);
+        assert!(body.starts_with("Invalid JSON: "));
+    }
+}
@@ -1,7 +1,9 @@
+pub mod extract;
 pub mod response;
-use std::future::Future;
+use std::{any::Any, future::Future};
 
 use async_trait::async_trait;
+pub use extract::handler;
 pub use response::*;
 
 use crate::message::Message;
@@ -12,6 +14,14 @@ pub type Request = Message;
 #[async_trait]
 pub trait Service<State>: Send + Sync + 'static {
     async fn call(&self, req: Request, state: State) -> ResponseResult;
+
+    /// Maps a response given to a request of the service from outside of it,
+    /// like the system error of a timeout or of a panic.
+    /// A [`crate::router::Router`] maps it with its layers, as the response of
+    /// any of its routes. By default, it is kept as is.
+    fn map_response(&self, response: ResponseResult) -> ResponseResult {
+        response
+    }
 }
 
 #[async_trait]
@@ -25,3 +35,56 @@ where
         (self)(req, state).await
     }
 }
+
+/// A panic caught while a service was processing a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Panic {
+    /// Address of the request
+    pub address: String,
+    /// Header of the request
+    pub header: String,
+    /// Message given to the panic, if it was a string
+    pub message: String,
+}
+
+impl Panic {
+    pub(crate) fn new(address: String, header: String, payload: Box<dyn Any + Send>) -> Panic {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => (*message).into(),
+                Err(_) => "<non-string panic payload>".into(),
+            },
+        };
+
+        Panic {
+            address,
+            header,
+            message,
+        }
+    }
+
+    /// Reply used when no other is configured with
+    /// [`crate::config::Config::on_panic`]. It does not reveal the panic.
+    pub fn default_response(&self) -> Response {
+        Response {
+            header: "Internal error".into(),
+            body: "The request could not be processed".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_message() {
+        let panic =
+            |payload: Box<dyn Any + Send>| Panic::new("".into(), "".into(), payload).message;
+
+        assert_eq!(panic(Box::new("static")), "static");
+        assert_eq!(panic(Box::new(String::from("formatted"))), "formatted");
+        assert_eq!(panic(Box::new(42)), "<non-string panic payload>");
+    }
+}
@@ -169,6 +169,14 @@ impl Response {
     pub fn none() -> ResponseResult {
         Ok(None)
     }
+
+    /// Reply to a request whose service took longer than allowed.
+    pub(crate) fn timeout() -> Response {
+        Response {
+            header: "Timeout".into(),
+            body: "The request took too long to be processed".into(),
+        }
+    }
 }
 
 #[cfg(test)]
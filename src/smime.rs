@@ -0,0 +1,353 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use mail_parser::{Message as EmailParser, MimeHeaders};
+use openssl::{
+    base64,
+    error::ErrorStack,
+    nid::Nid,
+    pkcs7::{Pkcs7, Pkcs7Flags},
+    pkey::{PKey, Private},
+    stack::Stack,
+    x509::{store::X509StoreBuilder, X509Ref, X509},
+};
+
+use crate::{
+    message::{Message, Signer as MessageSigner},
+    mime::{self, split_content, with_crlf},
+    transports::imap::read_body,
+};
+
+/// Certificates of the authorities trusted to verify the S/MIME signatures
+/// of the received messages, set with [`crate::config::Config::smime`].
+///
+/// The body of a signed message is replaced by the signed content, and the
+/// certificate that verified its signature is set as its
+/// [`Message::signer`]. A signature that does not verify leaves the signer
+/// unset.
+#[derive(Clone, Default)]
+pub struct TrustStore {
+    certificates: Vec<X509>,
+    system: bool,
+}
+
+impl TrustStore {
+    /// Trusts the authorities of the system, besides the given ones.
+    pub fn system(mut self) -> Self {
+        self.system = true;
+        self
+    }
+
+    /// Trusts all the certificates of a PEM file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<TrustStore> {
+        TrustStore::default().certificates(fs::read(path)?)
+    }
+
+    /// Trusts the certificates, in PEM.
+    pub fn certificates(mut self, pem: impl AsRef<[u8]>) -> io::Result<Self> {
+        self.certificates
+            .extend(X509::stack_from_pem(pem.as_ref()).map_err(invalid)?);
+        Ok(self)
+    }
+
+    /// Verifies a signed email.
+    /// Returns the signed content, and its signer if verified.
+    fn verify(&self, email: &[u8]) -> Result<(Vec<u8>, Option<MessageSigner>), ErrorStack> {
+        let (pkcs7, detached) = Pkcs7::from_smime(email)?;
+
+        let mut store = X509StoreBuilder::new()?;
+        if self.system {
+            store.set_default_paths()?;
+        }
+        for certificate in &self.certificates {
+            store.add_cert(certificate.clone())?;
+        }
+        let store = store.build();
+
+        let certificates = Stack::new()?;
+        let mut content = Vec::new();
+        let verified = pkcs7.verify(
+            &certificates,
+            &store,
+            detached.as_deref(),
+            Some(&mut content),
+            Pkcs7Flags::empty(),
+        );
+
+        match verified {
+            Ok(()) => {
+                let signers = pkcs7.signers(&certificates, Pkcs7Flags::empty())?;
+                Ok((content, signers.iter().next().map(signer)))
+            }
+            // The content of a detached signature is readable anyway
+            Err(err) => match detached {
+                Some(content) => {
+                    log::debug!("smime: signature not verified: {}", err);
+                    Ok((content, None))
+                }
+                None => Err(err),
+            },
+        }
+    }
+}
+
+/// Signs the sent messages with S/MIME, set with
+/// [`crate::transports::Smtp::smime()`].
+#[derive(Clone)]
+pub struct Signer {
+    certificate: X509,
+    key: PKey<Private>,
+    chain: Vec<X509>,
+}
+
+impl Signer {
+    /// Creates a signer from a certificate, followed by its chain if any, and
+    /// its private key, both in PEM.
+    pub fn new(certificates: impl AsRef<[u8]>, key: impl AsRef<[u8]>) -> io::Result<Signer> {
+        let mut chain = X509::stack_from_pem(certificates.as_ref()).map_err(invalid)?;
+        if chain.is_empty() {
+            return Err(invalid("no certificate"));
+        }
+
+        Ok(Signer {
+            certificate: chain.remove(0),
+            key: PKey::private_key_from_pem(key.as_ref()).map_err(invalid)?,
+            chain,
+        })
+    }
+
+    /// Wraps the body of an email in a `multipart/signed` entity.
+    pub(crate) fn sign(&self, email: &[u8]) -> io::Result<Vec<u8>> {
+        let (mut fields, entity) = split_content(email);
+
+        let signature = (|| {
+            let mut chain = Stack::new()?;
+            for certificate in &self.chain {
+                chain.push(certificate.clone())?;
+            }
+
+            Pkcs7::sign(
+                &self.certificate,
+                &self.key,
+                &chain,
+                &entity,
+                Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY,
+            )?
+            .to_der()
+        })()
+        .map_err(invalid)?;
+
+        let signature = base64::encode_block(&signature);
+        let lines = signature.as_bytes().chunks(76).collect::<Vec<_>>();
+
+        fields.extend(mime::signed(
+            &entity,
+            "application/pkcs7-signature",
+            "sha-256",
+            "Content-Type: application/pkcs7-signature; name=\"smime.p7s\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             Content-Disposition: attachment; filename=\"smime.p7s\"\r\n",
+            &String::from_utf8_lossy(&lines.join(&b"\r\n"[..])),
+        ));
+
+        Ok(fields)
+    }
+}
+
+/// Verifies the message if it is signed with S/MIME.
+/// Nothing is done for a message without its email.
+pub(crate) fn verify_message(message: &mut Message, trust: &TrustStore) {
+    let Some(raw) = &message.raw else {
+        return;
+    };
+
+    if !EmailParser::parse(raw).is_some_and(|email| is_signed(&email)) {
+        return;
+    }
+
+    let opened = trust
+        .verify(&with_crlf(raw))
+        .map_err(invalid)
+        .and_then(|(content, signer)| {
+            let content =
+                EmailParser::parse(&content).ok_or_else(|| invalid("unreadable content"))?;
+            Ok((read_body(&content), signer))
+        });
+
+    match opened {
+        Ok((body, signer)) => {
+            if signer.is_none() {
+                log::warn!("smime: signature of '{}' not verified", message.address);
+            }
+            message.body = body;
+            message.signer = signer;
+        }
+        Err(err) => log::warn!(
+            "smime: message of '{}' not opened: {}",
+            message.address,
+            err
+        ),
+    }
+}
+
+/// A `multipart/signed` email with a PKCS #7 signature, or an
+/// `application/pkcs7-mime` one with the signed data inside (RFC 8551).
+fn is_signed(email: &EmailParser) -> bool {
+    let Some(content_type) = email.root_part().content_type() else {
+        return false;
+    };
+
+    let attribute = |name: &str| content_type.attribute(name).unwrap_or_default();
+    let subtype = content_type.subtype().unwrap_or_default();
+
+    match content_type.ctype().to_ascii_lowercase().as_str() {
+        "multipart" => {
+            subtype.eq_ignore_ascii_case("signed")
+                && attribute("protocol")
+                    .to_ascii_lowercase()
+                    .ends_with("pkcs7-signature")
+        }
+        "application" => {
+            subtype.to_ascii_lowercase().ends_with("pkcs7-mime")
+                && attribute("smime-type").eq_ignore_ascii_case("signed-data")
+        }
+        _ => false,
+    }
+}
+
+fn signer(certificate: &X509Ref) -> MessageSigner {
+    let subject = certificate
+        .subject_name()
+        .entries()
+        .filter_map(|entry| {
+            let name = entry.object().nid().short_name().ok()?;
+            let value = entry.data().to_string().ok()?;
+            Some(format!("{}={}", name, value))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut addresses = certificate
+        .subject_name()
+        .entries_by_nid(Nid::PKCS9_EMAILADDRESS)
+        .filter_map(|entry| entry.data().to_string().ok())
+        .collect::<Vec<_>>();
+
+    for name in certificate.subject_alt_names().into_iter().flatten() {
+        if let Some(address) = name.email() {
+            addresses.push(address.into());
+        }
+    }
+
+    MessageSigner::Smime {
+        certificate: certificate.to_der().unwrap_or_default(),
+        subject,
+        addresses,
+    }
+}
+
+fn invalid(err: impl ToString) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        rsa::Rsa,
+        x509::{extension::SubjectAlternativeName, X509NameBuilder},
+    };
+
+    use super::*;
+    use crate::{
+        message::{Kind, Part},
+        transports::smtp::build_email,
+    };
+
+    /// A self-signed certificate for the address, and its key, in PEM.
+    fn certificate(address: &str) -> (Vec<u8>, Vec<u8>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "Server").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let alt_name = SubjectAlternativeName::new()
+            .email(address)
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(alt_name).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (
+            builder.build().to_pem().unwrap(),
+            key.private_key_to_pem_pkcs8().unwrap(),
+        )
+    }
+
+    fn message(raw: Option<Vec<u8>>) -> Message {
+        Message {
+            address: "server@a.com".into(),
+            header: "Hi".into(),
+            body: vec![Part {
+                kind: Kind::Text,
+                content: "Hello\n".into(),
+            }],
+            raw,
+            ..Default::default()
+        }
+    }
+
+    fn signed(signer: &Signer) -> Vec<u8> {
+        let email = build_email("server@a.com", "client@b.com", &message(None))
+            .write_to_vec()
+            .unwrap();
+        signer.sign(&email).unwrap()
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let (certificate, key) = certificate("server@a.com");
+        let signer = Signer::new(&certificate, key).unwrap();
+
+        let mut received = message(Some(signed(&signer)));
+        received.body.clear();
+        verify_message(
+            &mut received,
+            &TrustStore::default().certificates(&certificate).unwrap(),
+        );
+
+        assert_eq!(received.text_iter().next().unwrap().content, b"Hello\r\n");
+        assert!(matches!(
+            received.signer,
+            Some(MessageSigner::Smime { subject, addresses, .. })
+                if subject == "CN=Server" && addresses == ["server@a.com"]
+        ));
+
+        // Not trusted: the content is read, but not its signer
+        let mut received = message(Some(signed(&signer)));
+        received.body.clear();
+        verify_message(&mut received, &TrustStore::default());
+
+        assert_eq!(received.text_iter().next().unwrap().content, b"Hello\r\n");
+        assert_eq!(received.signer, None);
+    }
+}
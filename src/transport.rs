@@ -13,6 +13,20 @@ pub trait Transport: Sync + Send {
     type Error: Error + Send + Sync + 'static;
 
     async fn connect(&self) -> Result<Self::Connection, Self::Error>;
+
+    /// Tells if an error of [`Transport::connect`] comes from the server
+    /// rejecting the credentials, which retrying will not fix.
+    /// By default, no error is considered as such.
+    fn is_auth_error(_err: &Self::Error) -> bool {
+        false
+    }
+
+    /// Email address of the account used by the transport, if known.
+    /// The server never answers the messages coming from it.
+    /// By default, it is unknown.
+    fn address(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Represents a connection that can send messages.
@@ -21,6 +35,14 @@ pub trait Sender: Sized + Send {
     type Error: Error + Send + Sync + 'static;
 
     async fn send(&mut self, msg: &Message) -> Result<(), Self::Error>;
+
+    /// Tells if an error of [`Sender::send`] will happen again no matter how
+    /// many times the message is resent, i.e. the recipient does not exist.
+    /// Such a message is given up instead of retried through a new connection.
+    /// By default, no error is considered as such.
+    fn is_permanent(_err: &Self::Error) -> bool {
+        false
+    }
 }
 
 /// Represents a connection that can receive messages.
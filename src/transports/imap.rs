@@ -1,6 +1,7 @@
 use std::{
     collections::HashSet,
     net::{Shutdown, TcpStream},
+    str,
     sync::Arc,
     time::Duration,
 };
@@ -8,9 +9,9 @@ use std::{
 use async_trait::async_trait;
 use imap::{
     types::{Flag, Uid, UnsolicitedResponse},
-    Client, ClientBuilder, Session,
+    Client, ClientBuilder, Connection, Session,
 };
-use mail_parser::{Addr, HeaderValue, Message as EmailParser, MimeHeaders};
+use mail_parser::{Addr, ContentType, HeaderValue, Message as EmailParser, MimeHeaders};
 use native_tls::{TlsConnector, TlsStream};
 use tokio::{
     runtime::Handle,
@@ -18,7 +19,9 @@ use tokio::{
 };
 
 use crate::{
-    message::{Kind, Message, Part},
+    authentication::{AuthenticationResults, AUTHSERV_ID},
+    bounce::Bounce,
+    message::{Automatic, Kind, Message, Part},
     transport::{Receiver, Transport},
 };
 
@@ -35,7 +38,7 @@ use crate::{
 /// Refreshing often turns the keepalive into a liveness probe: every period the
 /// client must write `DONE` and read the answer, which fails fast when the
 /// connection is no longer usable.
-const IDLE_REFRESH: Duration = Duration::from_secs(2 * 60);
+pub(super) const IDLE_REFRESH: Duration = Duration::from_secs(2 * 60);
 
 /// Keyword used to mark the messages that can not be interpreted.
 ///
@@ -43,7 +46,7 @@ const IDLE_REFRESH: Duration = Duration::from_secs(2 * 60);
 /// understood (and so, never answered) would lose it silently. Marking it in
 /// the server instead means it is downloaded only once, and that the mark
 /// survives reconnections and restarts.
-const UNPROCESSABLE_KEYWORD: &str = "mailfred-unprocessable";
+pub(super) const UNPROCESSABLE_KEYWORD: &str = "mailfred-unprocessable";
 
 #[derive(Clone)]
 pub struct Imap {
@@ -109,6 +112,19 @@ impl Transport for Imap {
             ready_to_recv,
         })
     }
+
+    /// The only commands sent while connecting are `LOGIN` and `SELECT`, and
+    /// not all the servers give a reason when they refuse them. A refused
+    /// `SELECT`, for a folder that does not exist, is also counted here:
+    /// like wrong credentials, it is a setting that retrying will not fix.
+    fn is_auth_error(err: &imap::Error) -> bool {
+        matches!(err, imap::Error::No(_))
+    }
+
+    /// The user, if it is a full email address.
+    fn address(&self) -> Option<&str> {
+        self.user.contains('@').then_some(self.user.as_str())
+    }
 }
 
 fn listener(
@@ -255,13 +271,68 @@ fn read_address(header: &HeaderValue) -> Option<String> {
     }
 }
 
-fn read_email(email_raw: &[u8]) -> Option<Message> {
+pub(super) fn read_email(email_raw: &[u8]) -> Option<Message> {
     let email = EmailParser::parse(email_raw)?;
 
     let subject = email.subject().unwrap_or_default().into();
 
     let from = read_address(email.from())?;
 
+    Some(Message {
+        header: subject,
+        body: read_body(&email),
+        id: email.message_id().map(Into::into),
+        automatic: read_automatic(&email, &from),
+        bounce: read_bounce(&email),
+        authentication: read_authentication(&email),
+        signer: None,
+        recipients: read_recipients(&email),
+        raw: Some(email_raw.into()),
+        address: from,
+    })
+}
+
+/// Reads every address the email was sent to. `Delivered-To` is added by the
+/// receiving server, and it is the only one naming the alias or the list that
+/// delivered it, when that address is not in `To` nor in `Cc`.
+fn read_recipients(email: &EmailParser) -> Vec<String> {
+    fn all<'a>(header: &'a HeaderValue) -> Vec<&'a Addr<'a>> {
+        match header {
+            HeaderValue::Address(addr) => vec![addr],
+            HeaderValue::AddressList(addrs) => addrs.iter().collect(),
+            HeaderValue::Group(group) => group.addresses.iter().collect(),
+            HeaderValue::GroupList(groups) => groups
+                .iter()
+                .flat_map(|group| group.addresses.iter())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    let mut recipients = Vec::<String>::new();
+    let addresses = all(email.to())
+        .into_iter()
+        .chain(all(email.cc()))
+        .filter_map(|addr| Some(addr.address.as_deref()?.to_string()));
+    let delivered = email
+        .headers_raw()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Delivered-To"))
+        .map(|(_, value)| value.trim().trim_matches(['<', '>']).to_string());
+
+    for address in addresses.chain(delivered) {
+        if !recipients
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(&address))
+        {
+            recipients.push(address);
+        }
+    }
+
+    recipients
+}
+
+/// Reads the text bodies and the attachments of an email, or of a part.
+pub(crate) fn read_body(email: &EmailParser) -> Vec<Part> {
     let mut body = Vec::default();
 
     for part in email.text_bodies() {
@@ -284,11 +355,102 @@ fn read_email(email_raw: &[u8]) -> Option<Message> {
         }
     }
 
-    Some(Message {
-        address: from,
-        header: subject,
-        body,
-    })
+    body
+}
+
+/// Reads every `Authentication-Results` header, from the topmost.
+/// The ones claiming to come from mailfred itself are left out.
+fn read_authentication(email: &EmailParser) -> Vec<AuthenticationResults> {
+    email
+        .headers_raw()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Authentication-Results"))
+        .filter_map(|(_, value)| AuthenticationResults::parse(value))
+        .filter(|results| !results.authserv_id.eq_ignore_ascii_case(AUTHSERV_ID))
+        .collect()
+}
+
+/// Reads a delivery status notification: a `multipart/report` whose
+/// `report-type` is `delivery-status` (RFC 3464 and RFC 6533).
+fn read_bounce(email: &EmailParser) -> Option<Bounce> {
+    let is = |content_type: Option<&ContentType>, c_type: &str, subtypes: &[&str]| {
+        content_type.is_some_and(|content_type| {
+            content_type.ctype().eq_ignore_ascii_case(c_type)
+                && content_type.subtype().is_some_and(|subtype| {
+                    subtypes
+                        .iter()
+                        .any(|expected| subtype.eq_ignore_ascii_case(expected))
+                })
+        })
+    };
+
+    let content_type = email.content_type();
+    let report_type = content_type.and_then(|content_type| content_type.attribute("report-type"));
+    if !is(content_type, "multipart", &["report"])
+        || !report_type.is_some_and(|report_type| report_type.ends_with("delivery-status"))
+    {
+        return None;
+    }
+
+    let status = email.attachments().find(|part| {
+        is(
+            part.content_type(),
+            "message",
+            &["delivery-status", "global-delivery-status"],
+        )
+    })?;
+
+    // The original message comes either complete or only its headers
+    let original_id = email.attachments().find_map(|part| match part.message() {
+        Some(original) => original.message_id().map(Into::into),
+        None if is(
+            part.content_type(),
+            "text",
+            &["rfc822-headers", "global-headers"],
+        ) =>
+        {
+            EmailParser::parse(part.contents())?
+                .message_id()
+                .map(Into::into)
+        }
+        None => None,
+    });
+
+    Bounce::parse(str::from_utf8(status.contents()).ok()?, original_id)
+}
+
+/// Finds out if an email was sent automatically, from the headers that the
+/// programs sending them are expected to add (RFC 3834), and the ones that
+/// many of them add instead.
+fn read_automatic(email: &EmailParser, from: &str) -> Option<Automatic> {
+    let header = |name| {
+        email
+            .header_raw(name)
+            .map(|value| value.trim().to_ascii_lowercase())
+    };
+
+    if let Some(value) = header("Auto-Submitted").filter(|value| value != "no") {
+        return Some(Automatic::Submitted(value));
+    }
+
+    if let Some(value) =
+        header("Precedence").filter(|value| ["bulk", "list", "junk"].contains(&value.as_str()))
+    {
+        return Some(Automatic::Precedence(value));
+    }
+
+    if header("X-Autoreply").is_some() {
+        return Some(Automatic::Autoreply);
+    }
+
+    let local_part = from.split('@').next().unwrap_or_default();
+    if header("Return-Path").is_some_and(|value| value == "<>")
+        || local_part.eq_ignore_ascii_case("mailer-daemon")
+        || read_bounce(email).is_some()
+    {
+        return Some(Automatic::MailSystem);
+    }
+
+    None
 }
 
 impl Imap {
@@ -303,6 +465,78 @@ impl Imap {
 
         Ok(())
     }
+
+    /// Appends a raw email to the folder of this transport, marked as seen.
+    pub fn append(&self, content: &[u8]) -> imap::Result<()> {
+        let mut session = self.append_session()?;
+        append(&mut session, &self.folder, content)?;
+        session.logout()
+    }
+
+    fn append_session(&self) -> imap::Result<Session<Connection>> {
+        let client = ClientBuilder::new(&self.domain, self.port).connect()?;
+        client.login(&self.user, &self.password).map_err(|e| e.0)
+    }
+}
+
+fn append(session: &mut Session<Connection>, folder: &str, content: &[u8]) -> imap::Result<()> {
+    session.append(folder, content).flag(Flag::Seen).finish()?;
+    Ok(())
+}
+
+/// Appends emails to the folder of a transport from a thread of its own,
+/// without waiting for them to be appended.
+/// The session is kept open between them, instead of logging in for each one.
+pub(crate) struct Appender {
+    tx: std::sync::mpsc::Sender<Vec<u8>>,
+}
+
+impl Appender {
+    /// The thread ends once the appender is dropped and its emails appended.
+    pub(crate) fn new(imap: Imap) -> Appender {
+        let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+
+        std::thread::spawn(move || {
+            let mut session = None;
+
+            for content in rx {
+                // The server may have closed a session kept open for long,
+                // so a failure is retried once through a new one.
+                let mut appended = Ok(());
+                for _ in 0..2 {
+                    appended = match &mut session {
+                        Some(session) => append(session, &imap.folder, &content),
+                        None => imap
+                            .append_session()
+                            .and_then(|new| append(session.insert(new), &imap.folder, &content)),
+                    };
+
+                    match appended {
+                        Ok(()) => break,
+                        Err(_) => session = None,
+                    }
+                }
+
+                // The email is already sent at this point. A failure here must
+                // not be reported as a failure of the sending, or the email
+                // would be sent again.
+                if let Err(err) = appended {
+                    log::warn!("smtp: sent message not saved in '{}': {}", imap.folder, err);
+                }
+            }
+
+            if let Some(mut session) = session {
+                session.logout().ok();
+            }
+        });
+
+        Appender { tx }
+    }
+
+    /// Appends an email, once the previous ones are appended.
+    pub(crate) fn append(&self, content: Vec<u8>) {
+        self.tx.send(content).ok();
+    }
 }
 
 pub struct ImapConnection {
@@ -379,4 +613,117 @@ mod tests {
         let msg = email("From: a@b.com").unwrap();
         assert_eq!(msg.header, "");
     }
+
+    #[test]
+    fn message_id_is_read_as_the_id() {
+        let msg = email("From: a@b.com\r\nMessage-ID: <1234@b.com>").unwrap();
+        assert_eq!(msg.id.as_deref(), Some("1234@b.com"));
+
+        let msg = email("From: a@b.com").unwrap();
+        assert_eq!(msg.id, None);
+    }
+
+    #[test]
+    fn authentication_results_are_read() {
+        let msg = email(
+            "Authentication-Results: mx.b.com; spf=pass smtp.mailfrom=a@b.com\r\n\
+             Authentication-Results: forged.com; dmarc=pass\r\n\
+             Authentication-Results: mailfred; dkim=pass header.d=b.com\r\n\
+             From: a@b.com",
+        )
+        .unwrap();
+
+        let ids = msg
+            .authentication
+            .iter()
+            .map(|results| results.authserv_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["mx.b.com", "forged.com"]);
+        assert!(msg.authentication[0].authenticates("a@b.com"));
+        assert!(msg.raw.is_some());
+
+        let msg = email("From: a@b.com").unwrap();
+        assert_eq!(msg.authentication, vec![]);
+    }
+
+    #[test]
+    fn recipients_are_read() {
+        let msg = email(
+            "Delivered-To: <bot+billing@b.com>\r\n\
+             From: a@b.com\r\n\
+             To: Bot <bot@b.com>, c@b.com\r\n\
+             Cc: Team: d@b.com;\r\n\
+             Delivered-To: BOT@b.com",
+        )
+        .unwrap();
+
+        assert_eq!(
+            msg.recipients,
+            ["bot@b.com", "c@b.com", "d@b.com", "bot+billing@b.com"]
+        );
+    }
+
+    fn automatic(headers: &str) -> Option<Automatic> {
+        email(&format!("From: a@b.com\r\n{headers}"))
+            .unwrap()
+            .automatic
+    }
+
+    #[test]
+    fn automatic_messages() {
+        assert_eq!(automatic(""), None);
+        assert_eq!(automatic("Auto-Submitted: no"), None);
+        assert_eq!(automatic("Precedence: first-class"), None);
+        assert_eq!(
+            automatic("Auto-Submitted: Auto-Replied"),
+            Some(Automatic::Submitted("auto-replied".into()))
+        );
+        assert_eq!(
+            automatic("Precedence: bulk"),
+            Some(Automatic::Precedence("bulk".into()))
+        );
+        assert_eq!(automatic("X-Autoreply: yes"), Some(Automatic::Autoreply));
+        assert_eq!(automatic("Return-Path: <>"), Some(Automatic::MailSystem));
+
+        let bounce = email("From: MAILER-DAEMON@b.com").unwrap();
+        assert_eq!(bounce.automatic, Some(Automatic::MailSystem));
+    }
+
+    #[test]
+    fn delivery_status_notification() {
+        let msg = email(
+            "From: Mail Delivery System <mailer-daemon@mx.b.com>\r\n\
+             Content-Type: multipart/report; report-type=delivery-status; boundary=\"XX\"\r\n\
+             \r\n\
+             --XX\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             Your message could not be delivered.\r\n\
+             --XX\r\n\
+             Content-Type: message/delivery-status\r\n\
+             \r\n\
+             Reporting-MTA: dns; mx.b.com\r\n\
+             \r\n\
+             Final-Recipient: rfc822; nobody@b.com\r\n\
+             Action: failed\r\n\
+             Status: 5.1.1\r\n\
+             \r\n\
+             --XX\r\n\
+             Content-Type: text/rfc822-headers\r\n\
+             \r\n\
+             From: bot@a.com\r\n\
+             Message-ID: <1234@a.com>\r\n\
+             \r\n\
+             --XX--",
+        )
+        .unwrap();
+
+        let bounce = msg.bounce.unwrap();
+        assert_eq!(bounce.recipient, "nobody@b.com");
+        assert_eq!(bounce.status, "5.1.1");
+        assert_eq!(bounce.original_id.as_deref(), Some("1234@a.com"));
+        assert_eq!(msg.automatic, Some(Automatic::MailSystem));
+
+        assert_eq!(email("From: a@b.com").unwrap().bounce, None);
+    }
 }
@@ -0,0 +1,248 @@
+use std::{collections::HashSet, future::Future, io};
+
+use async_imap::{
+    error::{Error, Result},
+    types::{Flag, Uid},
+    Client, Session,
+};
+use async_trait::async_trait;
+use futures_util::{Stream, TryStreamExt};
+use tokio::net::TcpStream;
+use tokio_native_tls::{native_tls, TlsConnector, TlsStream};
+
+use super::imap::{read_email, Imap, IDLE_REFRESH, UNPROCESSABLE_KEYWORD};
+use crate::{
+    message::Message,
+    transport::{Receiver, Transport},
+};
+
+type ImapSession = Session<TlsStream<TcpStream>>;
+
+/// Asynchronous version of the [`Imap`] transport.
+///
+/// [`Imap`] parks a blocking thread per connection, and it needs a
+/// multi-threaded runtime to wait for it. This transport runs the listener in
+/// the task that receives the messages instead, so it can be used from any
+/// runtime, `current_thread` included, and many folders can be read without
+/// spending a thread on each one.
+/// ```no_run
+/// use mailfred::{
+///     service::Response,
+///     transport::Connector,
+///     transports::{AsyncImap, Gmail},
+/// };
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> Result<(), anyhow::Error> {
+///     let (imap, smtp) = Gmail::new("user", "1234").split();
+///
+///     let connector = (AsyncImap::from(imap), smtp);
+///     mailfred::serve(connector, (), |_, _| async { Response::none() }).await
+/// }
+/// ```
+#[derive(Clone)]
+pub struct AsyncImap(pub Imap);
+
+impl From<Imap> for AsyncImap {
+    fn from(imap: Imap) -> Self {
+        AsyncImap(imap)
+    }
+}
+
+#[async_trait]
+impl Transport for AsyncImap {
+    const NAME: &'static str = "imap";
+
+    type Connection = AsyncImapConnection;
+    type Error = Error;
+
+    async fn connect(&self) -> Result<AsyncImapConnection> {
+        let Imap {
+            domain,
+            port,
+            user,
+            password,
+            folder,
+        } = &self.0;
+
+        let tcp = TcpStream::connect((domain.as_str(), *port)).await?;
+
+        let tls = TlsConnector::from(native_tls::TlsConnector::new().map_err(io::Error::other)?)
+            .connect(domain, tcp)
+            .await
+            .map_err(io::Error::other)?;
+
+        // The port is expected to be an implicit TLS one (993), so the
+        // greeting comes already through the encrypted stream.
+        let mut client = Client::new(tls);
+        client.read_response().await?.ok_or(Error::ConnectionLost)?;
+
+        let mut session = client.login(user, password).await.map_err(|(e, _)| e)?;
+
+        session.select(folder).await?;
+
+        Ok(AsyncImapConnection {
+            session: Some(session),
+            pending: Vec::default(),
+            expunge_required: false,
+            unprocessable: HashSet::default(),
+        })
+    }
+
+    /// See [`Imap`], which counts the same errors.
+    fn is_auth_error(err: &Error) -> bool {
+        matches!(err, Error::No(_))
+    }
+
+    fn address(&self) -> Option<&str> {
+        self.0.address()
+    }
+}
+
+pub struct AsyncImapConnection {
+    session: Option<ImapSession>,
+    /// UIDs of the last fetch not processed yet, in reverse order
+    pending: Vec<Uid>,
+    expunge_required: bool,
+    /// Backs up the servers that do not support custom keywords.
+    /// See the listener of [`Imap`] for the details.
+    unprocessable: HashSet<Uid>,
+}
+
+#[async_trait]
+impl Receiver for AsyncImapConnection {
+    type Error = Error;
+
+    async fn recv(&mut self) -> Result<Message> {
+        // The session is out of the connection while a message is awaited.
+        // If this future is dropped in the middle of a command, the protocol
+        // is left in an unknown state, and the session is lost with it instead
+        // of being used again.
+        let session = self.session.take().ok_or(Error::ConnectionLost)?;
+        let (session, msg) = self.listen(session).await?;
+        self.session = Some(session);
+
+        Ok(msg)
+    }
+}
+
+impl AsyncImapConnection {
+    /// Same algorithm as the listener of [`Imap`], but stopping at each
+    /// message: the next call continues where the previous one left.
+    async fn listen(&mut self, mut session: ImapSession) -> Result<(ImapSession, Message)> {
+        loop {
+            while let Some(uid) = self.pending.pop() {
+                match fetch_email(&mut session, uid).await? {
+                    Some(msg) => {
+                        // Nothing is awaited after marking it, so once marked,
+                        // the message always reaches the caller.
+                        drain(session.uid_store(uid.to_string(), "+FLAGS (\\Deleted)")).await?;
+                        self.expunge_required = true;
+
+                        return Ok((session, msg));
+                    }
+                    None => self.mark_as_unprocessable(&mut session, uid).await,
+                }
+            }
+
+            if self.expunge_required {
+                drain(session.expunge()).await?;
+                self.expunge_required = false;
+            }
+
+            // Only the flags are asked here, and UIDs are used instead of
+            // sequence numbers. See the listener of `Imap` for the reasons.
+            let fetches = session
+                .uid_fetch("1:*", "(UID FLAGS)")
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            // A message can be already flagged as deleted if the connection
+            // fell before the folder was expunged.
+            self.expunge_required = fetches
+                .iter()
+                .any(|fetch| fetch.flags().any(|flag| flag == Flag::Deleted));
+
+            self.pending = fetches
+                .iter()
+                .filter(|fetch| {
+                    !fetch.flags().any(|flag| match flag {
+                        Flag::Deleted => true,
+                        Flag::Custom(keyword) => keyword == UNPROCESSABLE_KEYWORD,
+                        _ => false,
+                    })
+                })
+                .filter_map(|fetch| fetch.uid)
+                .filter(|uid| !self.unprocessable.contains(uid))
+                .rev()
+                .collect();
+
+            if self.pending.is_empty() && !self.expunge_required {
+                session = idle(session).await?;
+            }
+        }
+    }
+
+    async fn mark_as_unprocessable(&mut self, session: &mut ImapSession, uid: Uid) {
+        log::warn!(
+            "imap: message with uid {} can not be read, marking it to not be processed again",
+            uid
+        );
+
+        // Not all the servers accept custom keywords. If the mark can not be
+        // stored, the message is only skipped for this connection.
+        let stored = drain(session.uid_store(
+            uid.to_string(),
+            format!("+FLAGS ({})", UNPROCESSABLE_KEYWORD),
+        ))
+        .await;
+
+        if let Err(err) = stored {
+            log::debug!("imap: the folder does not accept keywords: {}", err);
+        }
+
+        self.unprocessable.insert(uid);
+    }
+}
+
+/// Waits in `IDLE` until the folder changes or [`IDLE_REFRESH`] expires.
+///
+/// Any news from the server ends the wait, not only the arrival of messages:
+/// reading the flags of the folder again is cheap, and it saves telling apart
+/// the different ways each server has to notify a new message.
+async fn idle(session: ImapSession) -> Result<ImapSession> {
+    let mut idle = session.idle();
+    idle.init().await?;
+
+    let (wait, _interrupt) = idle.wait_with_timeout(IDLE_REFRESH);
+    wait.await?;
+
+    // This is the liveness probe: a dead connection would never answer the
+    // `DONE`, and without a timeout the wait for it would never end.
+    tokio::time::timeout(IDLE_REFRESH, idle.done())
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
+
+/// Downloads a single message.
+/// Returns `None` if the message is gone or can not be interpreted.
+async fn fetch_email(session: &mut ImapSession, uid: Uid) -> Result<Option<Message>> {
+    let fetches = session
+        .uid_fetch(uid.to_string(), "RFC822")
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    Ok(fetches
+        .iter()
+        .find_map(|fetch| fetch.body())
+        .and_then(read_email))
+}
+
+/// Consumes the responses of a command that are not needed.
+async fn drain<T>(
+    responses: impl Future<Output = Result<impl Stream<Item = Result<T>>>>,
+) -> Result<()> {
+    responses.await?.try_for_each(|_| async { Ok(()) }).await
+}
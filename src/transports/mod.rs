@@ -1,17 +1,25 @@
 #[cfg(feature = "imap")]
 pub mod imap;
 
+#[cfg(feature = "imap-async")]
+pub mod imap_async;
+
 #[cfg(feature = "smtp")]
 pub mod smtp;
 
+#[cfg(all(feature = "imap", feature = "smtp"))]
+pub mod presets;
+
 #[cfg(feature = "imap")]
 pub use self::imap::Imap;
+#[cfg(feature = "imap-async")]
+pub use self::imap_async::AsyncImap;
 #[cfg(feature = "smtp")]
 pub use self::smtp::Smtp;
 
 #[cfg(all(feature = "imap", feature = "smtp"))]
 pub mod gmail {
-    use super::{Imap, Smtp};
+    use super::{presets::Preset, Imap, Smtp};
     use crate::transport::Connector;
 
     pub struct Gmail {
@@ -33,25 +41,14 @@ pub mod gmail {
         type Outbound = Smtp;
 
         fn split(self) -> (Self::Inbound, Self::Outbound) {
-            let imap = Imap {
-                domain: "imap.gmail.com".into(),
-                port: 993,
-                user: format!("{}@gmail.com", self.username),
-                password: self.password.clone(),
-                folder: "inbox".into(),
-            };
-
-            let smtp = Smtp {
-                domain: "smtp.gmail.com".into(),
-                port: 587,
-                user: format!("{}@gmail.com", self.username),
-                password: self.password,
-            };
-
-            (imap, smtp)
+            Preset::gmail()
+                .account(format!("{}@gmail.com", self.username), self.password)
+                .split()
         }
     }
 }
 
 #[cfg(all(feature = "imap", feature = "smtp"))]
 pub use gmail::Gmail;
+#[cfg(all(feature = "imap", feature = "smtp"))]
+pub use presets::Preset;
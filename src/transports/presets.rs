@@ -0,0 +1,194 @@
+use super::{smtp::Tls, Imap, Smtp};
+use crate::transport::Connector;
+
+/// Settings of an email provider: where its servers are, how to connect to
+/// them and how its folders are named.
+///
+/// The IMAP connection is always secured with implicit TLS, which is the only
+/// mode supported by [`Imap`].
+/// ```no_run
+/// use mailfred::{service::Response, transports::presets::Preset};
+///
+/// # async fn run() -> Result<(), anyhow::Error> {
+/// let account = Preset::fastmail().account("user@fastmail.com", "1234");
+///
+/// mailfred::serve(account, (), |_, _| async { Response::none() }).await
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub imap_domain: String,
+    pub imap_port: u16,
+    pub smtp_domain: String,
+    pub smtp_port: u16,
+    pub smtp_tls: Tls,
+    /// Folder read by the service
+    pub inbox_folder: String,
+    /// Folder where the provider stores the sent messages
+    pub sent_folder: String,
+}
+
+impl Preset {
+    /// Settings for a provider not listed here.
+    /// It assumes the standard ports: 993 for IMAP and 587 with `STARTTLS`
+    /// for SMTP, which can be changed through the fields if needed.
+    pub fn custom(imap_domain: impl Into<String>, smtp_domain: impl Into<String>) -> Preset {
+        Preset {
+            imap_domain: imap_domain.into(),
+            imap_port: 993,
+            smtp_domain: smtp_domain.into(),
+            smtp_port: 587,
+            smtp_tls: Tls::StartTls,
+            inbox_folder: "INBOX".into(),
+            sent_folder: "Sent".into(),
+        }
+    }
+
+    /// Gmail.
+    /// The sent folder is the one of an account in english, because Gmail
+    /// translates the names of its folders.
+    pub fn gmail() -> Preset {
+        Preset {
+            sent_folder: "[Gmail]/Sent Mail".into(),
+            ..Preset::custom("imap.gmail.com", "smtp.gmail.com")
+        }
+    }
+
+    /// Outlook, Hotmail and Office365
+    pub fn outlook() -> Preset {
+        Preset {
+            sent_folder: "Sent Items".into(),
+            ..Preset::custom("outlook.office365.com", "smtp.office365.com")
+        }
+    }
+
+    /// Fastmail
+    pub fn fastmail() -> Preset {
+        Preset {
+            smtp_port: 465,
+            smtp_tls: Tls::Implicit,
+            ..Preset::custom("imap.fastmail.com", "smtp.fastmail.com")
+        }
+    }
+
+    /// Yahoo Mail
+    pub fn yahoo() -> Preset {
+        Preset {
+            smtp_port: 465,
+            smtp_tls: Tls::Implicit,
+            ..Preset::custom("imap.mail.yahoo.com", "smtp.mail.yahoo.com")
+        }
+    }
+
+    /// iCloud Mail
+    pub fn icloud() -> Preset {
+        Preset {
+            sent_folder: "Sent Messages".into(),
+            ..Preset::custom("imap.mail.me.com", "smtp.mail.me.com")
+        }
+    }
+
+    /// Creates a connector to an account of this provider.
+    /// Most of the providers expect the full email address as user.
+    pub fn account(self, user: impl Into<String>, password: impl Into<String>) -> Account {
+        Account {
+            preset: self,
+            user: user.into(),
+            password: password.into(),
+            save_sent: false,
+        }
+    }
+}
+
+/// An account of a provider described by a [`Preset`].
+#[derive(Clone)]
+pub struct Account {
+    pub preset: Preset,
+    pub user: String,
+    pub password: String,
+    /// Appends the sent messages to the sent folder of the provider.
+    /// See [`Smtp::save_sent`].
+    pub save_sent: bool,
+}
+
+impl Account {
+    /// Appends the sent messages to the sent folder of the provider.
+    pub fn save_sent(self) -> Account {
+        Account {
+            save_sent: true,
+            ..self
+        }
+    }
+
+    /// IMAP transport reading from the given folder of the account.
+    pub fn imap(&self, folder: impl Into<String>) -> Imap {
+        Imap {
+            domain: self.preset.imap_domain.clone(),
+            port: self.preset.imap_port,
+            user: self.user.clone(),
+            password: self.password.clone(),
+            folder: folder.into(),
+        }
+    }
+
+    /// SMTP transport sending from the account.
+    pub fn smtp(&self) -> Smtp {
+        let smtp = Smtp::new(
+            self.preset.smtp_domain.clone(),
+            self.preset.smtp_port,
+            self.user.clone(),
+            self.password.clone(),
+        )
+        .tls(self.preset.smtp_tls);
+
+        match self.save_sent {
+            true => smtp.save_sent(self.sent()),
+            false => smtp,
+        }
+    }
+
+    /// IMAP transport reading from the sent folder of the account.
+    /// Useful to keep that folder clean with [`crate::spawn_consumer`].
+    pub fn sent(&self) -> Imap {
+        self.imap(self.preset.sent_folder.clone())
+    }
+}
+
+impl Connector for Account {
+    type Inbound = Imap;
+    type Outbound = Smtp;
+
+    fn split(self) -> (Self::Inbound, Self::Outbound) {
+        (self.imap(self.preset.inbox_folder.clone()), self.smtp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_transports() {
+        let account = Preset::fastmail().account("user@fastmail.com", "1234");
+
+        let sent = account.sent();
+        assert_eq!(sent.domain, "imap.fastmail.com");
+        assert_eq!(sent.folder, "Sent");
+
+        let (imap, smtp) = account.split();
+        assert_eq!(imap.port, 993);
+        assert_eq!(imap.folder, "INBOX");
+        assert_eq!(imap.user, "user@fastmail.com");
+        assert_eq!(smtp.domain, "smtp.fastmail.com");
+        assert_eq!((smtp.port, smtp.tls), (465, Tls::Implicit));
+        assert!(smtp.save_sent.is_none());
+    }
+
+    #[test]
+    fn account_saving_sent() {
+        let account = Preset::outlook().account("user@outlook.com", "1234");
+
+        let smtp = account.save_sent().smtp();
+        assert_eq!(smtp.save_sent.unwrap().folder, "Sent Items");
+    }
+}
@@ -1,21 +1,161 @@
-use std::io;
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    io,
+};
 
 use async_trait::async_trait;
-use mail_builder::{mime::MimePart, MessageBuilder as EmailBuilder};
-use mail_send::{self as smtp, SmtpClient, SmtpClientBuilder};
+use mail_builder::{headers::text::Text, mime::MimePart, MessageBuilder as EmailBuilder};
+use mail_send::{self as smtp, smtp::message::IntoMessage, SmtpClient, SmtpClientBuilder};
 use tokio::net::TcpStream;
 use tokio_rustls::client::TlsStream;
 
+#[cfg(feature = "imap")]
+use super::{imap::Appender, Imap};
+#[cfg(feature = "dkim")]
+use crate::authentication::dkim::Signer;
+#[cfg(feature = "pgp")]
+use crate::openpgp::Keyring;
+#[cfg(feature = "smime")]
+use crate::smime;
 use crate::{
-    message::{Kind, Message},
+    message::{Automatic, Kind, Message},
     transport::{Sender, Transport},
 };
 
+/// How the SMTP connection is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tls {
+    /// The connection starts in plain text and is upgraded with `STARTTLS`,
+    /// usually on port 587.
+    #[default]
+    StartTls,
+    /// The connection is encrypted from the first byte, usually on port 465.
+    Implicit,
+}
+
+/// Error of sending a message through an [`SmtpConnection`].
+#[derive(Debug)]
+pub enum SendError {
+    /// The SMTP server refused the message, or the connection failed.
+    Smtp(smtp::Error),
+    /// The message could not be protected before sending it, e.g. with
+    /// OpenPGP, because there is no key to encrypt it, or with S/MIME, because
+    /// the key does not match the certificate. It fails the same way every
+    /// time it is sent.
+    Protect(io::Error),
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Smtp(err) => write!(f, "{}", err),
+            SendError::Protect(err) => write!(f, "Message not protected: {}", err),
+        }
+    }
+}
+
+impl Error for SendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SendError::Smtp(err) => Some(err),
+            SendError::Protect(err) => Some(err),
+        }
+    }
+}
+
+impl From<smtp::Error> for SendError {
+    fn from(err: smtp::Error) -> Self {
+        SendError::Smtp(err)
+    }
+}
+
+/// SMTP transport, built with [`Smtp::new`].
+/// Its optional behaviors are enabled with its methods, which depend on the
+/// enabled features of the crate.
+#[derive(Clone)]
 pub struct Smtp {
     pub domain: String,
     pub port: u16,
+    pub tls: Tls,
     pub user: String,
     pub password: String,
+    #[cfg(feature = "imap")]
+    pub(crate) save_sent: Option<Imap>,
+    #[cfg(feature = "dkim")]
+    dkim: Option<Signer>,
+    #[cfg(feature = "pgp")]
+    pgp: Option<Keyring>,
+    #[cfg(feature = "smime")]
+    smime: Option<smime::Signer>,
+}
+
+impl Smtp {
+    /// An SMTP transport, with the connection upgraded with `STARTTLS`.
+    pub fn new(
+        domain: impl Into<String>,
+        port: u16,
+        user: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Smtp {
+        Smtp {
+            domain: domain.into(),
+            port,
+            tls: Tls::default(),
+            user: user.into(),
+            password: password.into(),
+            #[cfg(feature = "imap")]
+            save_sent: None,
+            #[cfg(feature = "dkim")]
+            dkim: None,
+            #[cfg(feature = "pgp")]
+            pgp: None,
+            #[cfg(feature = "smime")]
+            smime: None,
+        }
+    }
+
+    /// Sets how the connection is secured.
+    pub fn tls(mut self, tls: Tls) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Appends every sent message to the folder of an IMAP transport.
+    /// Some providers, like Gmail, store by themselves the messages sent
+    /// through SMTP in their sent folder, but most of them do not, and there
+    /// would be no record of the replies otherwise.
+    /// The messages are appended in the background, through a session kept
+    /// open by the connection, so sending does not wait for them.
+    #[cfg(feature = "imap")]
+    pub fn save_sent(mut self, imap: Imap) -> Self {
+        self.save_sent = Some(imap);
+        self
+    }
+
+    /// Signs every sent message with DKIM, for the receivers to trust that it
+    /// comes from the domain, and not to take it for spam.
+    #[cfg(feature = "dkim")]
+    pub fn dkim(mut self, signer: Signer) -> Self {
+        self.dkim = Some(signer);
+        self
+    }
+
+    /// Signs and encrypts the sent messages with OpenPGP, as configured in
+    /// the keyring. They are protected before being signed with DKIM.
+    #[cfg(feature = "pgp")]
+    pub fn pgp(mut self, keyring: Keyring) -> Self {
+        self.pgp = Some(keyring);
+        self
+    }
+
+    /// Signs the sent messages with S/MIME, after protecting them with
+    /// OpenPGP and before signing them with DKIM.
+    #[cfg(feature = "smime")]
+    pub fn smime(mut self, signer: smime::Signer) -> Self {
+        self.smime = Some(signer);
+        self
+    }
 }
 
 #[async_trait]
@@ -31,7 +171,7 @@ impl Transport for Smtp {
             // `mail_send` reports it as a plain string with no error variant
             // of its own to map it to.
             .map_err(|err| smtp::Error::Io(io::Error::other(err)))?
-            .implicit_tls(false)
+            .implicit_tls(self.tls == Tls::Implicit)
             .credentials((self.user.as_ref(), self.password.as_ref()))
             .connect()
             .await?;
@@ -39,39 +179,145 @@ impl Transport for Smtp {
         Ok(SmtpConnection {
             client,
             origin: self.user.clone(),
+            #[cfg(feature = "imap")]
+            save_sent: self.save_sent.clone().map(Appender::new),
+            #[cfg(feature = "dkim")]
+            dkim: self.dkim.clone(),
+            #[cfg(feature = "pgp")]
+            pgp: self.pgp.clone(),
+            #[cfg(feature = "smime")]
+            smime: self.smime.clone(),
         })
     }
+
+    /// The user, if it is a full email address.
+    fn address(&self) -> Option<&str> {
+        self.user.contains('@').then_some(self.user.as_str())
+    }
+
+    fn is_auth_error(err: &smtp::Error) -> bool {
+        matches!(
+            err,
+            smtp::Error::Auth(_)
+                | smtp::Error::AuthenticationFailed(_)
+                | smtp::Error::MissingCredentials
+                | smtp::Error::UnsupportedAuthMechanism
+        )
+    }
 }
 
 pub struct SmtpConnection {
     client: SmtpClient<TlsStream<TcpStream>>,
     origin: String,
+    #[cfg(feature = "imap")]
+    save_sent: Option<Appender>,
+    #[cfg(feature = "dkim")]
+    dkim: Option<Signer>,
+    #[cfg(feature = "pgp")]
+    pgp: Option<Keyring>,
+    #[cfg(feature = "smime")]
+    smime: Option<smime::Signer>,
 }
 
 #[async_trait]
 impl Sender for SmtpConnection {
-    type Error = smtp::Error;
+    type Error = SendError;
+
+    async fn send(&mut self, msg: &Message) -> Result<(), SendError> {
+        let email = build_email(&self.origin, &msg.address, msg);
+
+        // Built once to send and to save exactly the same bytes,
+        // `Message-ID` and `Date` included.
+        #[cfg_attr(
+            not(any(feature = "dkim", feature = "pgp", feature = "smime")),
+            allow(unused_mut)
+        )]
+        let mut email = email.into_message()?;
+
+        #[cfg(feature = "pgp")]
+        if let Some(keyring) = &self.pgp {
+            email.body = keyring
+                .protect(&email.body, &msg.address)
+                .map_err(SendError::Protect)?
+                .into();
+        }
+
+        #[cfg(feature = "smime")]
+        if let Some(signer) = &self.smime {
+            email.body = signer.sign(&email.body).map_err(SendError::Protect)?.into();
+        }
+
+        #[cfg(feature = "dkim")]
+        if let Some(signer) = &self.dkim {
+            email.body = signer.sign(&email.body).into();
+        }
+
+        if let Err(err) = self.client.send(email.clone()).await {
+            // A refused message leaves its transaction open in the server,
+            // which would refuse the next one because of it.
+            if let smtp::Error::UnexpectedReply(_) = err {
+                self.client.rset().await.ok();
+            }
+
+            return Err(err.into());
+        }
+
+        #[cfg(feature = "imap")]
+        if let Some(appender) = &self.save_sent {
+            appender.append(email.body.into_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Any `5xx` reply is permanent, according to RFC 5321,
+    /// and so is a message without a valid recipient,
+    /// or one that can not be protected.
+    fn is_permanent(err: &SendError) -> bool {
+        match err {
+            SendError::Smtp(smtp::Error::UnexpectedReply(reply)) => {
+                (500..600).contains(&reply.code)
+            }
+            SendError::Smtp(smtp::Error::MissingRcptTo) => true,
+            SendError::Smtp(_) => false,
+            SendError::Protect(_) => true,
+        }
+    }
+}
+
+/// Builds the email of a message, from and to the given addresses.
+pub(crate) fn build_email<'a>(from: &'a str, to: &'a str, msg: &'a Message) -> EmailBuilder<'a> {
+    let parts = msg
+        .body
+        .iter()
+        .cloned()
+        .map(|part| match part.kind {
+            Kind::Text => MimePart::new("text/plain", part.content),
+            Kind::Html => MimePart::new("text/html", part.content),
+            Kind::Attachment(name) => {
+                MimePart::new("application/octet-stream", part.content).attachment(name)
+            }
+        })
+        .collect::<Vec<MimePart>>();
+
+    let email = EmailBuilder::new()
+        .from(from)
+        .to(to)
+        .subject(msg.header.as_str())
+        .body(MimePart::new("multipart/mixed", parts));
+
+    let email = match &msg.id {
+        Some(id) => email.message_id(id.as_str()),
+        None => email,
+    };
 
-    async fn send(&mut self, msg: &Message) -> smtp::Result<()> {
-        let parts = msg
-            .body
-            .iter()
-            .cloned()
-            .map(|part| match part.kind {
-                Kind::Text => MimePart::new("text/plain", part.content),
-                Kind::Html => MimePart::new("text/html", part.content),
-                Kind::Attachment(name) => {
-                    MimePart::new("application/octet-stream", part.content).attachment(name)
-                }
-            })
-            .collect::<Vec<MimePart>>();
-
-        let email = EmailBuilder::new()
-            .from(self.origin.as_str())
-            .to(msg.address.as_str())
-            .subject(msg.header.as_str())
-            .body(MimePart::new("multipart/mixed", parts));
-
-        self.client.send(email).await
+    match &msg.automatic {
+        Some(Automatic::Submitted(value)) => {
+            email.header("Auto-Submitted", Text::new(value.as_str()))
+        }
+        Some(Automatic::Precedence(value)) => email.header("Precedence", Text::new(value.as_str())),
+        Some(Automatic::Autoreply) => email.header("X-Autoreply", Text::new("yes")),
+        // Nothing to tell: the return path is written by the server
+        Some(Automatic::MailSystem) | None => email,
     }
 }